@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use log::debug;
+
+/// Keeps track of open connections so a shutting down server can drain and close them
+#[derive(Default)]
+pub struct ConnectionTracker {
+    connections: Mutex<HashMap<u64, TrackedConnection>>,
+    connections_closed: Condvar,
+    next_id: AtomicU64,
+    shutting_down: AtomicBool
+}
+
+/// A tracked connection, removed from its tracker when dropped
+pub struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    id: u64
+}
+
+struct TrackedConnection {
+    tcp_stream: TcpStream,
+    idle: bool
+}
+
+impl ConnectionTracker {
+
+    /// Starts tracking a connection until the returned guard is dropped.
+    /// Connections are registered when they are accepted, so the ones still waiting for a worker are tracked too
+    pub fn register(self: &Arc<Self>, tcp_stream: &TcpStream) -> std::io::Result<ConnectionGuard> {
+        let tcp_stream = tcp_stream.try_clone()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.connections.lock().unwrap().insert(id, TrackedConnection { tcp_stream, idle: false });
+        Ok(ConnectionGuard {
+            tracker: self.clone(),
+            id
+        })
+    }
+
+    /// Returns how many connections are open, including the ones waiting for a worker
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    fn set_idle(&self, id: u64, idle: bool) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        if idle && self.is_shutting_down() {
+            return false
+        }
+        if let Some(connection) = connections.get_mut(&id) {
+            connection.idle = idle;
+        }
+        true
+    }
+
+    fn remove(&self, id: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.remove(&id);
+        if connections.is_empty() {
+            self.connections_closed.notify_all();
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Flags the server as shutting down and closes every connection waiting for its next request.
+    /// Connections in the middle of a request are left alone so they can finish
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        let connections = self.connections.lock().unwrap();
+        connections.values()
+            .filter(|connection| connection.idle)
+            .for_each(|connection| {
+                // Makes the pending read return end of stream, which ends the connection loop
+                connection.tcp_stream.shutdown(Shutdown::Read).ok();
+            });
+    }
+
+    /// Waits until every connection is closed, returning false if `timeout` passed first
+    pub fn wait_until_drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut connections = self.connections.lock().unwrap();
+
+        while !connections.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false
+            }
+            connections = self.connections_closed.wait_timeout(connections, remaining).unwrap().0;
+        }
+        true
+    }
+
+    /// Closes every remaining connection regardless of its state
+    pub fn close_all(&self) {
+        let connections = self.connections.lock().unwrap();
+        if !connections.is_empty() {
+            debug!("Force closing {} remaining connections", connections.len());
+        }
+        connections.values()
+            .for_each(|connection| {
+                connection.tcp_stream.shutdown(Shutdown::Both).ok();
+            });
+    }
+}
+
+impl ConnectionGuard {
+
+    /// Marks the connection as waiting for its next request.
+    /// Returns false if it should not wait because the server is shutting down
+    pub fn set_idle(&self) -> bool {
+        self.tracker.set_idle(self.id, true)
+    }
+
+    /// Marks the connection as handling a request
+    pub fn set_busy(&self) {
+        self.tracker.set_idle(self.id, false);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.remove(self.id);
+    }
+}
@@ -20,10 +20,35 @@ pub fn split_lines_by_bytes<'a>(bytes: &'a[u8], splitter: &[u8]) -> Vec<&'a[u8]>
     split_slices
 }
 
+/// Decodes the `%XX` escapes of a percent-encoded string, returning None if an escape is malformed
+pub fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+
+    while position < bytes.len() {
+        if bytes[position] == b'%' {
+            let hex = bytes.get(position + 1..position + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            position += 3;
+        } else {
+            decoded.push(bytes[position]);
+            position += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Returns the lowercase media type of a `Content-Type` value, without its parameters
+pub fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
 #[macro_export]
 macro_rules! create_enum_and_matchers {
     ($enum_name:ident, $($enum_options:ident),*) => {
-        #[allow(dead_code)]
+        #[allow(dead_code, clippy::upper_case_acronyms)]
         #[derive(Debug, Default, PartialEq)]
         pub enum $enum_name {
             #[default]
@@ -42,20 +67,21 @@ macro_rules! create_enum_and_matchers {
             }
         }
 
-        impl ToString for $enum_name {
-            #[allow(dead_code)]
-            fn to_string(&self) -> String {
-                match self {
-                    $(
-                      $enum_name::$enum_options => stringify!($enum_options).to_string(),
-                    )*
-                }
+        impl std::fmt::Display for $enum_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}",
+                    match self {
+                        $(
+                          $enum_name::$enum_options => stringify!($enum_options),
+                        )*
+                    }
+                )
             }
         }
     };
 
     ($enum_name:ident, $($enum_options:ident, $enum_values:expr, $enum_str:expr),*) => {
-        #[allow(dead_code)]
+        #[allow(dead_code, clippy::upper_case_acronyms)]
         #[derive(Debug, Default, PartialEq)]
         pub enum $enum_name {
             #[default]
@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+
+/// Size of the chunks a streamed body is read and sent in
+const STREAM_CHUNK_SIZE: usize = 8192;
+
+/// The body of a response, either held in memory or streamed from a reader while it is sent
+pub enum HttpBody {
+    /// Bytes held in memory. Static data is borrowed, everything else is owned
+    Bytes(Cow<'static, [u8]>),
+    /// Bytes read while sending the response. Without a length the body is sent with `Transfer-Encoding: chunked`
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>
+    }
+}
+
+impl HttpBody {
+    pub fn empty() -> Self {
+        HttpBody::default()
+    }
+
+    /// Creates a body that is read from `reader` while the response is sent.
+    /// If `length` is given, the reader has to provide at least that many bytes
+    pub fn from_reader<R: Read + Send + 'static>(reader: R, length: Option<u64>) -> Self {
+        HttpBody::Stream {
+            reader: Box::new(reader),
+            length
+        }
+    }
+
+    /// Creates a body of unknown length that is sent chunk by chunk as the iterator produces them
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static
+    {
+        HttpBody::from_reader(ChunkReader { chunks: chunks.into_iter(), current: Vec::new(), position: 0 }, None)
+    }
+
+    /// Returns the bytes of a body held in memory, None for streamed bodies
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            HttpBody::Bytes(bytes) => Some(bytes),
+            HttpBody::Stream { .. } => None
+        }
+    }
+
+    /// Returns the body as a string if it is held in memory and valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    /// Returns the length of the body if it is known before sending it
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            HttpBody::Bytes(bytes) => Some(bytes.len() as u64),
+            HttpBody::Stream { length, .. } => *length
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    pub fn is_stream(&self) -> bool {
+        matches!(self, HttpBody::Stream { .. })
+    }
+
+    /// Writes the body. A streamed body is read until its length is reached, or until the reader is exhausted if
+    /// its length is unknown. With `chunked` the body is framed with the chunked transfer coding
+    pub fn write_to<W: Write>(self, writer: &mut W, chunked: bool) -> std::io::Result<()> {
+        let mut reader: Box<dyn Read + Send> = match self {
+            HttpBody::Bytes(bytes) if !chunked => return writer.write_all(&bytes),
+            HttpBody::Bytes(bytes) => Box::new(std::io::Cursor::new(bytes)),
+            HttpBody::Stream { reader, length: Some(length) } if !chunked => {
+                let written = std::io::copy(&mut reader.take(length), writer)?;
+                // The announced Content-Length can not be taken back, the response is broken
+                if written < length {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("Streamed body ended after {} of {} bytes", written, length)
+                    ))
+                }
+                return Ok(())
+            }
+            HttpBody::Stream { reader, length: None } if !chunked => {
+                std::io::copy(&mut { reader }, writer)?;
+                return Ok(())
+            }
+            HttpBody::Stream { reader, .. } => reader
+        };
+
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            };
+            write!(writer, "{:X}\r\n", read)?;
+            writer.write_all(&buffer[..read])?;
+            writer.write_all(b"\r\n")?;
+        }
+        writer.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl Default for HttpBody {
+    fn default() -> Self {
+        HttpBody::Bytes(Cow::Borrowed(&[]))
+    }
+}
+
+impl Debug for HttpBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpBody::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            HttpBody::Stream { length, .. } => f.debug_struct("Stream").field("length", length).finish_non_exhaustive()
+        }
+    }
+}
+
+impl From<&str> for HttpBody {
+    fn from(value: &str) -> Self {
+        HttpBody::Bytes(Cow::Owned(value.as_bytes().to_vec()))
+    }
+}
+
+impl From<&String> for HttpBody {
+    fn from(value: &String) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl From<String> for HttpBody {
+    fn from(value: String) -> Self {
+        HttpBody::Bytes(Cow::Owned(value.into_bytes()))
+    }
+}
+
+impl From<Vec<u8>> for HttpBody {
+    fn from(value: Vec<u8>) -> Self {
+        HttpBody::Bytes(Cow::Owned(value))
+    }
+}
+
+impl From<&'static [u8]> for HttpBody {
+    fn from(value: &'static [u8]) -> Self {
+        HttpBody::Bytes(Cow::Borrowed(value))
+    }
+}
+
+impl<const N: usize> From<&'static [u8; N]> for HttpBody {
+    fn from(value: &'static [u8; N]) -> Self {
+        HttpBody::Bytes(Cow::Borrowed(value))
+    }
+}
+
+/// Reads the chunks of an iterator one after another
+struct ChunkReader<I: Iterator<Item = Vec<u8>>> {
+    chunks: I,
+    current: Vec<u8>,
+    position: usize
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0)
+            }
+        }
+
+        let read = buf.len().min(self.current.len() - self.position);
+        buf[..read].copy_from_slice(&self.current[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_body::*;
+
+    fn written(body: HttpBody, chunked: bool) -> std::io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        body.write_to(&mut bytes, chunked)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn should_build_body_from_strings_and_bytes() {
+        assert_eq!(HttpBody::from("text").as_str(), Some("text"));
+        assert_eq!(HttpBody::from(String::from("text")).as_bytes(), Some(b"text".as_slice()));
+        assert_eq!(HttpBody::from(vec![0xff, 0x00]).len(), Some(2));
+        assert_eq!(HttpBody::from(b"static").as_bytes(), Some(b"static".as_slice()));
+        assert_eq!(HttpBody::from(vec![0xff]).as_str(), None);
+    }
+
+    #[test]
+    fn should_write_stream_with_known_length() {
+        let body = HttpBody::from_reader(&b"hello world"[..], Some(5));
+
+        assert_eq!(written(body, false).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn should_fail_when_stream_is_shorter_than_its_length() {
+        let body = HttpBody::from_reader(&b"hello"[..], Some(10));
+
+        assert_eq!(written(body, false).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn should_write_chunks_with_chunked_encoding() {
+        let body = HttpBody::from_chunks(vec![b"hello ".to_vec(), Vec::new(), b"world".to_vec()]);
+
+        assert_eq!(written(body, true).unwrap(), b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+}
@@ -0,0 +1,466 @@
+use std::io::{Read, Write};
+use flate2::Compression as FlateLevel;
+use flate2::read::{GzEncoder, MultiGzDecoder, ZlibDecoder, ZlibEncoder};
+use log::error;
+use crate::http_server::helper::media_type;
+use crate::http_server::http_body::HttpBody;
+use crate::http_server::http_error::{HttpServerError, Result};
+use crate::http_server::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::http_middleware::{Middleware, Next};
+use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
+use crate::http_server::lib::RequestContext;
+
+/// Bodies smaller than this are not worth compressing
+const DEFAULT_MIN_SIZE: u64 = 1024;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Content codings from RFC 9110, section 8.4.1, and brotli from RFC 7932
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Brotli,
+    Gzip,
+    Deflate,
+    Identity
+}
+
+impl ContentCoding {
+    /// Codings the server can compress with, in order of preference when the client has none
+    const SUPPORTED: [ContentCoding; 3] = [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate];
+
+    /// Looks up a coding by its case-insensitive name, `x-gzip` is an alias of `gzip`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "br" => Some(ContentCoding::Brotli),
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "deflate" => Some(ContentCoding::Deflate),
+            "identity" => Some(ContentCoding::Identity),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Brotli => "br",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Identity => "identity"
+        }
+    }
+
+    /// Wraps a reader so that reading from it returns the compressed bytes
+    pub fn encoder(&self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        match self {
+            ContentCoding::Brotli => Box::new(brotli::CompressorReader::new(reader, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW_SIZE)),
+            ContentCoding::Gzip => Box::new(GzEncoder::new(reader, FlateLevel::default())),
+            // The deflate content coding is the zlib format, not raw deflate
+            ContentCoding::Deflate => Box::new(ZlibEncoder::new(reader, FlateLevel::default())),
+            ContentCoding::Identity => reader
+        }
+    }
+
+    /// Wraps a reader of compressed bytes so that reading from it returns the decompressed bytes
+    pub fn decoder<'a, R: Read + 'a>(&self, reader: R) -> Box<dyn Read + 'a> {
+        match self {
+            ContentCoding::Brotli => Box::new(brotli::Decompressor::new(reader, BROTLI_BUFFER_SIZE)),
+            // Gzip data may consist of several members that are decompressed one after another
+            ContentCoding::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            ContentCoding::Deflate => Box::new(ZlibDecoder::new(reader)),
+            ContentCoding::Identity => Box::new(reader)
+        }
+    }
+
+    /// Decompresses bytes held in memory, failing with `DecodedBodyTooLarge` once more than `max_size` bytes come out
+    pub fn decode(&self, bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        self.decoder(bytes)
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|_| HttpServerError::InvalidContentEncoding)?;
+        if decoded.len() > max_size {
+            return Err(HttpServerError::DecodedBodyTooLarge)
+        }
+        Ok(decoded)
+    }
+
+    /// Compresses bytes held in memory
+    pub fn encode(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        match self {
+            ContentCoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(&mut compressed, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW_SIZE);
+                writer.write_all(bytes)?;
+                writer.flush()?;
+            }
+            ContentCoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(&mut compressed, FlateLevel::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+            ContentCoding::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(&mut compressed, FlateLevel::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+            ContentCoding::Identity => compressed.extend_from_slice(bytes)
+        }
+        Ok(compressed)
+    }
+}
+
+/// Parses an `Accept-Encoding` header into codings and their q-values, in the order they were sent.
+/// Codings with a malformed q-value are left out
+pub fn parse_accept_encoding(value: &str) -> Vec<(String, f32)> {
+    value.split(',')
+        .filter_map(|element| {
+            let mut parameters = element.split(';');
+            let coding = parameters.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None
+            }
+
+            let mut quality = 1.0;
+            for parameter in parameters {
+                let (name, value) = parameter.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse::<f32>().ok().filter(|quality| (0.0..=1.0).contains(quality))?;
+                }
+            }
+            Some((coding, quality))
+        })
+        .collect()
+}
+
+/// Picks the supported coding the client prefers, None if it accepts none of them.
+/// `*` stands for every coding not listed explicitly, ties are broken by the server's preference
+pub fn negotiate_encoding(accept_encoding: &str) -> Option<ContentCoding> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    let quality_of = |coding: ContentCoding| {
+        let listed = accepted.iter()
+            .find(|(name, _)| ContentCoding::from_name(name) == Some(coding))
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"));
+        listed.map_or(0.0, |(_, quality)| *quality)
+    };
+
+    ContentCoding::SUPPORTED.into_iter()
+        .map(|coding| (coding, quality_of(coding)))
+        .filter(|(_, quality)| *quality > 0.0)
+        // max_by returns the last of equal elements, reversing keeps the preferred coding on ties
+        .rev()
+        .max_by(|(_, first), (_, second)| first.total_cmp(second))
+        .map(|(coding, _)| coding)
+}
+
+/// Decompresses a request body sent with `Content-Encoding`, so handlers always see the original bytes.
+/// Codings are undone in the reverse order they were applied, afterwards `Content-Encoding` is removed and
+/// `Content-Length` is updated to the decoded size. Decoded bodies larger than `max_decoded_size` are rejected
+pub fn decode_request_body(http_request: &mut HttpRequest, max_decoded_size: usize) -> Result<()> {
+    let Some(content_encoding) = http_request.headers.get_joined("Content-Encoding") else { return Ok(()) };
+    let codings = content_encoding.split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| ContentCoding::from_name(name).ok_or(HttpServerError::UnsupportedContentEncoding))
+        .collect::<Result<Vec<ContentCoding>>>()?;
+
+    for coding in codings.iter().rev() {
+        http_request.body = coding.decode(&http_request.body, max_decoded_size)?;
+    }
+
+    http_request.headers.remove("Content-Encoding");
+    if http_request.headers.remove("Content-Length") {
+        let content_length = http_request.body.len().to_string();
+        http_request.headers.append("Content-Length", &content_length);
+    }
+    Ok(())
+}
+
+/// Compresses response bodies with the coding negotiated through `Accept-Encoding`.
+/// Register it with `HttpServerBuilder::add_middleware`, responses of handlers and later middlewares are compressed
+pub struct Compression {
+    min_size: u64,
+    content_type_filter: fn(&str) -> bool
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            content_type_filter: is_compressible_content_type
+        }
+    }
+
+    /// Sets the size in bytes below which bodies are sent uncompressed. Streamed bodies of unknown length are always compressed
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets which media types are compressed, the default covers text, JSON, JavaScript, XML, SVG and WebAssembly
+    pub fn content_type_filter(mut self, content_type_filter: fn(&str) -> bool) -> Self {
+        self.content_type_filter = content_type_filter;
+        self
+    }
+
+    /// Compresses the body of the response if it is eligible and the client accepts a supported coding
+    pub fn compress(&self, http_response: &mut HttpResponse, accept_encoding: Option<&str>) {
+        let Some(coding) = self.negotiate(http_response, accept_encoding) else { return };
+        let Some(body) = http_response.body.take() else { return };
+
+        let compressed_body = match body {
+            HttpBody::Bytes(bytes) => match coding.encode(&bytes) {
+                Ok(compressed) => HttpBody::from(compressed),
+                Err(err) => {
+                    error!("Failed to compress response body with {}: {}", coding.as_str(), err);
+                    http_response.body = Some(HttpBody::Bytes(bytes));
+                    return
+                }
+            },
+            // A reader may hold more bytes than the body announced, only those up to its length belong to it
+            HttpBody::Stream { reader, length: Some(length) } => HttpBody::from_reader(coding.encoder(Box::new(reader.take(length))), None),
+            HttpBody::Stream { reader, length: None } => HttpBody::from_reader(coding.encoder(reader), None)
+        };
+
+        http_response.set_body(compressed_body);
+        mark_encoded(http_response, coding);
+    }
+
+    /// Sets the headers `compress` would set without compressing the body, which a HEAD response drops anyway.
+    /// The length of the compressed body is unknown, so `Content-Length` is left out as RFC 9110, section 9.3.2 allows
+    pub fn compress_headers(&self, http_response: &mut HttpResponse, accept_encoding: Option<&str>) {
+        let Some(coding) = self.negotiate(http_response, accept_encoding) else { return };
+
+        http_response.body = None;
+        http_response.headers.remove("Content-Length");
+        mark_encoded(http_response, coding);
+    }
+
+    /// Picks the coding to compress the response with, None if it is sent as is
+    fn negotiate(&self, http_response: &mut HttpResponse, accept_encoding: Option<&str>) -> Option<ContentCoding> {
+        let is_eligible = http_response.status_code.allows_body()
+            && http_response.status_code != HttpStatusCode::PartialContent
+            && http_response.headers.get("Content-Encoding").is_none()
+            && http_response.headers.get("Content-Type").is_some_and(|content_type| (self.content_type_filter)(content_type));
+        if !is_eligible {
+            return None
+        }
+        // Caches have to keep the representations for different Accept-Encoding values apart
+        add_vary(http_response, "Accept-Encoding");
+
+        let body = http_response.body.as_ref()?;
+        let coding = accept_encoding.and_then(negotiate_encoding)?;
+        body.len().is_none_or(|length| length >= self.min_size).then_some(coding)
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, cx: &RequestContext, next: Next) -> HttpResponse {
+        let mut http_response = next.run(cx);
+        let accept_encoding = cx.http_request.headers.get_joined("Accept-Encoding");
+        if cx.http_request.request_line.method == HttpMethod::HEAD {
+            self.compress_headers(&mut http_response, accept_encoding.as_deref());
+        } else {
+            self.compress(&mut http_response, accept_encoding.as_deref());
+        }
+        http_response
+    }
+}
+
+/// Media types that usually shrink when compressed, already compressed formats like images are left out
+pub fn is_compressible_content_type(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "application/wasm" | "image/svg+xml"
+        )
+}
+
+fn mark_encoded(http_response: &mut HttpResponse, coding: ContentCoding) {
+    http_response.headers.insert("Content-Encoding", coding.as_str());
+    // The compressed bytes differ from the uncompressed ones, a strong validator would claim they are the same
+    if let Some(etag) = http_response.headers.get("ETag").filter(|etag| !etag.starts_with("W/")) {
+        let weak_etag = format!("W/{}", etag);
+        http_response.headers.insert("ETag", &weak_etag);
+    }
+}
+
+fn add_vary(http_response: &mut HttpResponse, header_name: &str) {
+    let vary = match http_response.headers.get("Vary") {
+        Some(vary) if vary.split(',').any(|name| name.trim().eq_ignore_ascii_case(header_name) || name.trim() == "*") => return,
+        Some(vary) => format!("{}, {}", vary, header_name),
+        None => header_name.to_string()
+    };
+    http_response.headers.insert("Vary", &vary);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::http_server::http_compression::*;
+
+    fn json_response(body: HttpBody) -> HttpResponse {
+        HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .add_header(("Content-Type", "application/json"))
+            .add_header(("ETag", "\"v1\""))
+            .body(body)
+            .build()
+    }
+
+    fn large_json() -> String {
+        format!("[{}]", vec!["{\"id\": 1}"; 200].join(","))
+    }
+
+    fn compressed_request(content_encoding: &str, body: Vec<u8>) -> HttpRequest {
+        let head = format!("POST /ingest HTTP/1.1\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n", content_encoding, body.len());
+        let mut http_request = HttpRequest::from_bytes(head.as_bytes()).unwrap();
+        http_request.body = body;
+        http_request
+    }
+
+    #[test]
+    fn should_decode_compressed_request_bodies() {
+        for coding in [ContentCoding::Gzip, ContentCoding::Deflate, ContentCoding::Brotli] {
+            let mut http_request = compressed_request(coding.as_str(), coding.encode(large_json().as_bytes()).unwrap());
+            decode_request_body(&mut http_request, 1024 * 1024).unwrap();
+
+            assert_eq!(http_request.body, large_json().into_bytes());
+            assert_eq!(http_request.headers.get("Content-Encoding"), None);
+            assert_eq!(http_request.headers.get("Content-Length"), Some(large_json().len().to_string().as_str()));
+        }
+    }
+
+    #[test]
+    fn should_decode_multiple_codings_in_reverse_order() {
+        let gzipped = ContentCoding::Gzip.encode(b"hello").unwrap();
+        let mut http_request = compressed_request("gzip, br", ContentCoding::Brotli.encode(&gzipped).unwrap());
+        decode_request_body(&mut http_request, 1024).unwrap();
+
+        assert_eq!(http_request.body, b"hello");
+    }
+
+    #[test]
+    fn should_reject_unknown_or_corrupt_encodings() {
+        let mut http_request = compressed_request("compress", b"hello".to_vec());
+        assert!(matches!(decode_request_body(&mut http_request, 1024), Err(HttpServerError::UnsupportedContentEncoding)));
+
+        let mut http_request = compressed_request("gzip", b"not gzip".to_vec());
+        assert!(matches!(decode_request_body(&mut http_request, 1024), Err(HttpServerError::InvalidContentEncoding)));
+    }
+
+    #[test]
+    fn should_limit_decoded_body_size() {
+        let bomb = ContentCoding::Gzip.encode(&vec![0; 1024 * 1024]).unwrap();
+        let mut http_request = compressed_request("gzip", bomb);
+
+        assert!(matches!(decode_request_body(&mut http_request, 64 * 1024), Err(HttpServerError::DecodedBodyTooLarge)));
+    }
+
+    #[test]
+    fn should_parse_accept_encoding_with_q_values() {
+        assert_eq!(
+            parse_accept_encoding("gzip;q=0.8, BR, identity;q=0, deflate;q=2"),
+            vec![("gzip".to_string(), 0.8), ("br".to_string(), 1.0), ("identity".to_string(), 0.0)]
+        );
+    }
+
+    #[test]
+    fn should_negotiate_preferred_encoding() {
+        assert_eq!(negotiate_encoding("gzip, deflate, br"), Some(ContentCoding::Brotli));
+        assert_eq!(negotiate_encoding("gzip;q=1, br;q=0.5"), Some(ContentCoding::Gzip));
+        assert_eq!(negotiate_encoding("br;q=0, *;q=0.1"), Some(ContentCoding::Gzip));
+        assert_eq!(negotiate_encoding("x-gzip"), Some(ContentCoding::Gzip));
+        assert_eq!(negotiate_encoding("identity"), None);
+        assert_eq!(negotiate_encoding("gzip;q=0"), None);
+    }
+
+    #[test]
+    fn should_compress_buffered_body() {
+        let mut http_response = json_response(large_json().into());
+        Compression::new().compress(&mut http_response, Some("gzip"));
+
+        assert_eq!(http_response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(http_response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(http_response.headers.get("ETag"), Some("W/\"v1\""));
+        let compressed = http_response.body_bytes().unwrap();
+        assert_eq!(http_response.headers.get("Content-Length"), Some(compressed.len().to_string().as_str()));
+
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, large_json());
+    }
+
+    #[test]
+    fn should_compress_streamed_body() {
+        let chunks = vec![large_json().into_bytes(), large_json().into_bytes()];
+        let mut http_response = json_response(HttpBody::from_chunks(chunks));
+        Compression::new().compress(&mut http_response, Some("br"));
+
+        assert_eq!(http_response.headers.get("Content-Encoding"), Some("br"));
+        assert!(http_response.is_chunked());
+
+        let mut bytes = Vec::new();
+        http_response.body.unwrap().write_to(&mut bytes, false).unwrap();
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(bytes.as_slice(), 4096).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, large_json().repeat(2));
+    }
+
+    #[test]
+    fn should_compress_only_declared_length_of_streamed_body() {
+        let content = large_json().repeat(2).into_bytes();
+        let length = large_json().len() as u64;
+        let mut http_response = json_response(HttpBody::from_reader(std::io::Cursor::new(content), Some(length)));
+        Compression::new().compress(&mut http_response, Some("gzip"));
+
+        let mut bytes = Vec::new();
+        http_response.body.unwrap().write_to(&mut bytes, false).unwrap();
+        let mut decompressed = String::new();
+        GzDecoder::new(bytes.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, large_json());
+    }
+
+    #[test]
+    fn should_set_headers_without_compressing_for_head() {
+        let mut http_response = json_response(large_json().into());
+        Compression::new().compress_headers(&mut http_response, Some("gzip"));
+
+        assert_eq!(http_response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(http_response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(http_response.headers.get("ETag"), Some("W/\"v1\""));
+        assert_eq!(http_response.headers.get("Content-Length"), None);
+        assert!(http_response.body.is_none());
+    }
+
+    #[test]
+    fn should_not_compress_small_or_ineligible_bodies() {
+        let mut http_response = json_response("{}".into());
+        Compression::new().compress(&mut http_response, Some("gzip"));
+        assert_eq!(http_response.headers.get("Content-Encoding"), None);
+        assert_eq!(http_response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(http_response.body_bytes(), Some(b"{}".as_slice()));
+
+        let mut http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .add_header(("Content-Type", "image/png"))
+            .body(large_json())
+            .build();
+        Compression::new().compress(&mut http_response, Some("gzip"));
+        assert_eq!(http_response.headers.get("Content-Encoding"), None);
+        assert_eq!(http_response.headers.get("Vary"), None);
+    }
+
+    #[test]
+    fn should_not_compress_without_accept_encoding() {
+        let mut http_response = json_response(large_json().into());
+        Compression::new().compress(&mut http_response, None);
+
+        assert_eq!(http_response.headers.get("Content-Encoding"), None);
+        assert_eq!(http_response.body_bytes(), Some(large_json().as_bytes()));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::http_server::http_date::parse_http_date;
+use crate::http_server::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
+
+/// An entity tag as sent in `ETag`, `If-Match` and `If-None-Match` headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String
+}
+
+impl EntityTag {
+    /// Creates a strong entity tag, the tag must not contain double quotes
+    pub fn strong(tag: &str) -> Self {
+        EntityTag { weak: false, tag: tag.to_string() }
+    }
+
+    /// Creates a weak entity tag, the tag must not contain double quotes
+    pub fn weak(tag: &str) -> Self {
+        EntityTag { weak: true, tag: tag.to_string() }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Two entity tags match strongly if both are strong and their tags are equal
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Two entity tags match weakly if their tags are equal, regardless of either being weak
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Parses a single entity tag like `"abc"` or `W/"abc"`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted_tag) = match value.strip_prefix("W/") {
+            Some(quoted_tag) => (true, quoted_tag),
+            None => (false, value)
+        };
+        let tag = quoted_tag.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None
+        }
+
+        Some(EntityTag { weak, tag: tag.to_string() })
+    }
+}
+
+impl Display for EntityTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+/// The value of an `If-Match` or `If-None-Match` header
+#[derive(Debug, PartialEq)]
+enum EntityTagCondition {
+    Any,
+    Tags(Vec<EntityTag>)
+}
+
+impl EntityTagCondition {
+    /// Parses the joined values of the header, returning None if any of the entity tags is malformed
+    fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(EntityTagCondition::Any)
+        }
+
+        value.split(',')
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .map(EntityTag::parse)
+            .collect::<Option<Vec<EntityTag>>>()
+            .map(EntityTagCondition::Tags)
+    }
+
+    fn matches(&self, etag: Option<&EntityTag>, compare: fn(&EntityTag, &EntityTag) -> bool) -> bool {
+        match (self, etag) {
+            (EntityTagCondition::Any, _) => true,
+            (EntityTagCondition::Tags(tags), Some(etag)) => tags.iter().any(|tag| compare(tag, etag)),
+            (EntityTagCondition::Tags(_), None) => false
+        }
+    }
+}
+
+/// Evaluates the conditional headers of a request against the current validators of the target resource,
+/// following the order of RFC 9110, section 13.2.2.
+///
+/// Returns the response to send instead of processing the request: 304 Not Modified for GET and HEAD requests whose
+/// cached representation is still current, or 412 Precondition Failed when a precondition does not hold.
+/// Returns None if the request should be processed normally
+pub fn evaluate_preconditions(http_request: &HttpRequest, etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> Option<HttpResponse> {
+    let headers = &http_request.headers;
+    let is_get_or_head = matches!(http_request.request_line.method, HttpMethod::GET | HttpMethod::HEAD);
+    // HTTP dates only have a resolution of seconds
+    let last_modified = last_modified.map(truncate_to_seconds);
+
+    if let Some(if_match) = headers.get_joined("If-Match") {
+        let condition = EntityTagCondition::parse(&if_match);
+        if !condition.is_some_and(|condition| condition.matches(etag, EntityTag::strong_eq)) {
+            return Some(HttpStatusCode::PreconditionFailed.into())
+        }
+    } else if let (Some(if_unmodified_since), Some(last_modified)) = (headers.get("If-Unmodified-Since").and_then(parse_http_date), last_modified) {
+        if last_modified > if_unmodified_since {
+            return Some(HttpStatusCode::PreconditionFailed.into())
+        }
+    }
+
+    if let Some(if_none_match) = headers.get_joined("If-None-Match") {
+        let condition = EntityTagCondition::parse(&if_none_match);
+        if condition.is_some_and(|condition| condition.matches(etag, EntityTag::weak_eq)) {
+            return Some(if is_get_or_head {
+                not_modified_response(etag, last_modified)
+            } else {
+                HttpStatusCode::PreconditionFailed.into()
+            })
+        }
+    } else if let (true, Some(if_modified_since), Some(last_modified)) = (is_get_or_head, headers.get("If-Modified-Since").and_then(parse_http_date), last_modified) {
+        if last_modified <= if_modified_since {
+            return Some(not_modified_response(etag, Some(last_modified)))
+        }
+    }
+
+    None
+}
+
+/// 304 responses repeat the validators a 200 response would have carried
+fn not_modified_response(etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> HttpResponse {
+    let mut builder = HttpResponse::builder().status_code(HttpStatusCode::NotModified);
+    if let Some(etag) = etag {
+        builder = builder.etag(etag);
+    }
+    if let Some(last_modified) = last_modified {
+        builder = builder.last_modified(last_modified);
+    }
+    builder.build()
+}
+
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(time, |duration| UNIX_EPOCH + Duration::from_secs(duration.as_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_conditional::*;
+
+    const LAST_MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn request(method: &str, header: &str) -> HttpRequest {
+        HttpRequest::from_bytes(format!("{} /resource HTTP/1.1\r\n{}\r\n\r\n", method, header).as_bytes()).unwrap()
+    }
+
+    fn status(http_request: &HttpRequest) -> Option<HttpStatusCode> {
+        let etag = EntityTag::strong("v2");
+        evaluate_preconditions(http_request, Some(&etag), parse_http_date(LAST_MODIFIED))
+            .map(|http_response| http_response.status_code)
+    }
+
+    #[test]
+    fn should_parse_entity_tags() {
+        assert_eq!(EntityTag::parse("\"abc\""), Some(EntityTag::strong("abc")));
+        assert_eq!(EntityTag::parse("W/\"abc\""), Some(EntityTag::weak("abc")));
+        assert_eq!(EntityTag::parse("abc"), None);
+        assert_eq!(EntityTag::weak("abc").to_string(), "W/\"abc\"");
+        assert!(EntityTag::weak("abc").weak_eq(&EntityTag::strong("abc")));
+        assert!(!EntityTag::weak("abc").strong_eq(&EntityTag::strong("abc")));
+    }
+
+    #[test]
+    fn should_answer_not_modified_for_matching_if_none_match() {
+        assert_eq!(status(&request("GET", "If-None-Match: \"v1\", W/\"v2\"")), Some(HttpStatusCode::NotModified));
+        assert_eq!(status(&request("HEAD", "If-None-Match: *")), Some(HttpStatusCode::NotModified));
+        assert_eq!(status(&request("GET", "If-None-Match: \"v1\"")), None);
+
+        let http_response = evaluate_preconditions(&request("GET", "If-None-Match: \"v2\""), Some(&EntityTag::strong("v2")), None).unwrap();
+        assert_eq!(http_response.headers.get("ETag"), Some("\"v2\""));
+    }
+
+    #[test]
+    fn should_fail_writes_for_matching_if_none_match() {
+        assert_eq!(status(&request("PUT", "If-None-Match: *")), Some(HttpStatusCode::PreconditionFailed));
+    }
+
+    #[test]
+    fn should_evaluate_if_modified_since() {
+        assert_eq!(status(&request("GET", &format!("If-Modified-Since: {}", LAST_MODIFIED))), Some(HttpStatusCode::NotModified));
+        assert_eq!(status(&request("GET", "If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT")), None);
+        assert_eq!(status(&request("GET", "If-Modified-Since: not a date")), None);
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(status(&request("GET", &format!("If-None-Match: \"v1\"\r\nIf-Modified-Since: {}", LAST_MODIFIED))), None);
+        assert_eq!(status(&request("POST", &format!("If-Modified-Since: {}", LAST_MODIFIED))), None);
+    }
+
+    #[test]
+    fn should_evaluate_if_match() {
+        assert_eq!(status(&request("PUT", "If-Match: \"v2\"")), None);
+        assert_eq!(status(&request("PUT", "If-Match: \"v1\"")), Some(HttpStatusCode::PreconditionFailed));
+        assert_eq!(status(&request("PUT", "If-Match: W/\"v2\"")), Some(HttpStatusCode::PreconditionFailed));
+        assert_eq!(status(&request("DELETE", "If-Match: *")), None);
+    }
+
+    #[test]
+    fn should_evaluate_if_unmodified_since() {
+        assert_eq!(status(&request("PUT", &format!("If-Unmodified-Since: {}", LAST_MODIFIED))), None);
+        assert_eq!(status(&request("PUT", "If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT")), Some(HttpStatusCode::PreconditionFailed));
+        // If-Match takes precedence over If-Unmodified-Since
+        assert_eq!(status(&request("PUT", "If-Match: \"v2\"\r\nIf-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT")), None);
+    }
+}
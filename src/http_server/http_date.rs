@@ -0,0 +1,141 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+/// HTTP-dates have four digit years, later years are rejected instead of overflowing the time calculation
+const MAX_YEAR: i64 = 9999;
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats a point in time as an IMF-fixdate, for example `Sun, 06 Nov 1994 08:49:37 GMT`.
+/// Times before the unix epoch are formatted as the epoch
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let days = seconds / SECONDS_PER_DAY;
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days as i64);
+    // The 1st of January 1970 was a thursday
+    let weekday = WEEKDAYS[((days + 4) % 7) as usize];
+
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        weekday, day, MONTHS[month as usize - 1], year,
+        seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60
+    )
+}
+
+/// Parses a HTTP-date in any of the three formats of RFC 9110, section 5.6.7:
+/// IMF-fixdate, the obsolete RFC 850 format and the asctime format.
+/// Returns None if the date is malformed, before the unix epoch or after the year 9999
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (year, month, day, time) = match parts.as_slice() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+            (year.parse().ok()?, parse_month(month)?, day.parse().ok()?, *time)
+        }
+        // Sunday, 06-Nov-94 08:49:37 GMT
+        [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+            let mut date_parts = date.split('-');
+            let day = date_parts.next()?.parse().ok()?;
+            let month = parse_month(date_parts.next()?)?;
+            let year: i64 = date_parts.next()?.parse().ok()?;
+            if date_parts.next().is_some() { return None }
+            // Two digit years more than 50 years in the future are in the past century
+            let year = if year < 50 { 2000 + year } else if year < 100 { 1900 + year } else { year };
+            (year, month, day, *time)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [_weekday, month, day, time, year] => {
+            (year.parse().ok()?, parse_month(month)?, day.parse().ok()?, *time)
+        }
+        _ => return None
+    };
+
+    let time_parts: Vec<u64> = time.split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let [hours, minutes, seconds] = time_parts.as_slice() else { return None };
+    // Leap seconds are allowed by the grammar
+    if !(1..=31).contains(&day) || *hours > 23 || *minutes > 59 || *seconds > 60 || !(1970..=MAX_YEAR).contains(&year) {
+        return None
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let seconds = days.checked_mul(SECONDS_PER_DAY)?
+        .checked_add(hours * 3600 + minutes * 60 + seconds)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+fn parse_month(month: &str) -> Option<u32> {
+    MONTHS.iter()
+        .position(|name| *name == month)
+        .map(|position| position as u32 + 1)
+}
+
+/// Converts days since the unix epoch into a (year, month, day) date of the proleptic gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Converts a date of the proleptic gregorian calendar into days since the unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_date::*;
+
+    const EXAMPLE_TIME: u64 = 784_111_777;
+
+    #[test]
+    fn should_format_imf_fixdate() {
+        assert_eq!(format_http_date(UNIX_EPOCH + Duration::from_secs(EXAMPLE_TIME)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format_http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format_http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn should_parse_all_date_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(EXAMPLE_TIME));
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn should_reject_malformed_dates() {
+        assert_eq!(parse_http_date("yesterday"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+    }
+
+    #[test]
+    fn should_reject_years_after_9999() {
+        assert!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+        assert_eq!(parse_http_date("Sat, 01 Jan 10000 00:00:00 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 300000000000 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 99999999999999 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 99999999999999"), None);
+    }
+}
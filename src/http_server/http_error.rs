@@ -1,10 +1,98 @@
+use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
+
 #[derive(Debug)]
 pub enum HttpServerError {
     InvalidHttpRequestStructure,
     InvalidRequestLineSyntax,
     HttpMethodNotFound,
+    /// The version is not of the form `HTTP/DIGIT.DIGIT`
     InvalidHttpVersionFormat,
-    HttpServerAlreadyRunning
+    /// A well-formed version other than HTTP/1.0 and HTTP/1.1
+    UnsupportedHttpVersion,
+    HttpServerAlreadyRunning,
+    InvalidContentLength,
+    BodyLengthMismatch,
+    /// The request body is larger than the server allows
+    BodyTooLarge,
+    InvalidChunkedEncoding,
+    UnsupportedTransferEncoding,
+    /// The request body was sent with a content coding the server can not decode
+    UnsupportedContentEncoding,
+    /// The request body could not be decoded with its content coding
+    InvalidContentEncoding,
+    /// The decoded request body is larger than the server allows
+    DecodedBodyTooLarge,
+    HeaderTooLarge,
+    /// A header field has no colon, whitespace before its colon or a name that is not a token
+    InvalidHeaderSyntax,
+    InvalidPercentEncoding,
+    EncodedSlashInPath,
+    /// The client closed the connection before sending another request
+    ConnectionClosed,
+    IoError(std::io::Error)
+}
+
+impl HttpServerError {
+    /// The status code a client receives when its request fails with this error
+    pub fn status_code(&self) -> HttpStatusCode {
+        match self {
+            HttpServerError::InvalidHttpRequestStructure
+            | HttpServerError::InvalidRequestLineSyntax
+            | HttpServerError::InvalidHttpVersionFormat
+            | HttpServerError::InvalidContentLength
+            | HttpServerError::BodyLengthMismatch
+            | HttpServerError::InvalidChunkedEncoding
+            | HttpServerError::InvalidContentEncoding
+            | HttpServerError::InvalidHeaderSyntax
+            | HttpServerError::InvalidPercentEncoding
+            | HttpServerError::EncodedSlashInPath => HttpStatusCode::BadRequest,
+            HttpServerError::HttpMethodNotFound
+            | HttpServerError::UnsupportedTransferEncoding => HttpStatusCode::NotImplemented,
+            HttpServerError::UnsupportedHttpVersion => HttpStatusCode::HttpVersionNotSupported,
+            HttpServerError::UnsupportedContentEncoding => HttpStatusCode::UnsupportedMediaType,
+            HttpServerError::BodyTooLarge
+            | HttpServerError::DecodedBodyTooLarge => HttpStatusCode::PayloadTooLarge,
+            HttpServerError::HeaderTooLarge => HttpStatusCode::RequestHeaderFieldsTooLarge,
+            HttpServerError::HttpServerAlreadyRunning
+            | HttpServerError::ConnectionClosed
+            | HttpServerError::IoError(_) => HttpStatusCode::InternalServerError
+        }
+    }
+}
+
+impl From<&HttpServerError> for HttpResponse {
+    fn from(value: &HttpServerError) -> Self {
+        let mut http_response: HttpResponse = value.status_code().into();
+        // Tells the client which codings it can use instead, RFC 9110, section 12.5.3
+        if let HttpServerError::UnsupportedContentEncoding = value {
+            http_response.headers.insert("Accept-Encoding", "gzip, deflate, br");
+        }
+        http_response
+    }
+}
+
+impl From<std::io::Error> for HttpServerError {
+    fn from(value: std::io::Error) -> Self {
+        HttpServerError::IoError(value)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, HttpServerError>;
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_error::*;
+
+    #[test]
+    fn should_map_errors_to_status_codes() {
+        assert_eq!(HttpServerError::InvalidRequestLineSyntax.status_code(), HttpStatusCode::BadRequest);
+        assert_eq!(HttpServerError::HttpMethodNotFound.status_code(), HttpStatusCode::NotImplemented);
+        assert_eq!(HttpServerError::InvalidHttpVersionFormat.status_code(), HttpStatusCode::BadRequest);
+        assert_eq!(HttpServerError::UnsupportedHttpVersion.status_code(), HttpStatusCode::HttpVersionNotSupported);
+        assert_eq!(HttpServerError::HeaderTooLarge.status_code(), HttpStatusCode::RequestHeaderFieldsTooLarge);
+        assert_eq!(HttpServerError::InvalidHeaderSyntax.status_code(), HttpStatusCode::BadRequest);
+        assert_eq!(HttpServerError::UnsupportedContentEncoding.status_code(), HttpStatusCode::UnsupportedMediaType);
+        assert_eq!(HttpServerError::BodyTooLarge.status_code(), HttpStatusCode::PayloadTooLarge);
+        assert_eq!(HttpServerError::DecodedBodyTooLarge.status_code(), HttpStatusCode::PayloadTooLarge);
+    }
+}
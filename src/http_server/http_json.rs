@@ -0,0 +1,153 @@
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::http_server::helper::media_type;
+use crate::http_server::http_request::HttpRequest;
+use crate::http_server::http_response::{HttpResponse, HttpResponseBuilder, HttpStatusCode};
+use crate::http_server::lib::RequestContext;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Reasons a request body could not be read as JSON
+#[derive(Debug)]
+pub enum JsonError {
+    /// The request does not declare a JSON `Content-Type`
+    UnsupportedMediaType,
+    /// The body is not valid JSON or does not fit the expected type
+    InvalidJson(serde_json::Error)
+}
+
+impl JsonError {
+    /// 415 if the body is not declared as JSON, 400 if it does not deserialize into the expected type
+    pub fn status_code(&self) -> HttpStatusCode {
+        match self {
+            JsonError::UnsupportedMediaType => HttpStatusCode::UnsupportedMediaType,
+            JsonError::InvalidJson(_) => HttpStatusCode::BadRequest
+        }
+    }
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType => write!(f, "expected Content-Type {}", JSON_CONTENT_TYPE),
+            JsonError::InvalidJson(err) => write!(f, "invalid JSON body: {}", err)
+        }
+    }
+}
+
+impl From<&JsonError> for HttpResponse {
+    fn from(value: &JsonError) -> Self {
+        HttpResponse::builder()
+            .status_code(value.status_code())
+            .add_header(("Content-Type", "text/plain; charset=utf-8"))
+            .body(value.to_string())
+            .build()
+    }
+}
+
+impl From<JsonError> for HttpResponse {
+    fn from(value: JsonError) -> Self {
+        (&value).into()
+    }
+}
+
+/// Deserializes the body of a request declaring `Content-Type: application/json` or any `+json` media type
+pub fn parse_json_body<T: DeserializeOwned>(http_request: &HttpRequest) -> Result<T, JsonError> {
+    let is_json = http_request.headers.get("Content-Type")
+        .is_some_and(is_json_content_type);
+    if !is_json {
+        return Err(JsonError::UnsupportedMediaType)
+    }
+
+    serde_json::from_slice(&http_request.body).map_err(JsonError::InvalidJson)
+}
+
+fn is_json_content_type(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    media_type == JSON_CONTENT_TYPE || media_type.ends_with("+json")
+}
+
+impl RequestContext<'_> {
+    /// Deserializes the JSON request body, the error converts into a 415 or 400 response
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        parse_json_body(self.http_request)
+    }
+}
+
+impl HttpResponseBuilder {
+    /// Serializes `value` as the body and sets `Content-Type: application/json`.
+    /// If the value can not be serialized the response becomes a 500 without a body
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(json) => self
+                .add_header(("Content-Type", JSON_CONTENT_TYPE))
+                .body(json),
+            Err(err) => {
+                error!("Failed to serialize JSON response: {}", err);
+                self.status_code(HttpStatusCode::InternalServerError)
+            }
+        }
+    }
+}
+
+impl HttpResponse {
+    /// Creates a 200 OK response with `value` serialized as JSON
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Self {
+        HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .json(value)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use crate::http_server::http_json::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32
+    }
+
+    fn request(content_type: &str, body: &str) -> HttpRequest {
+        let mut http_request = HttpRequest::from_bytes(format!("POST /users HTTP/1.1\r\nContent-Type: {}\r\n\r\n", content_type).as_bytes()).unwrap();
+        http_request.body = body.as_bytes().to_vec();
+        http_request
+    }
+
+    #[test]
+    fn should_parse_json_body() {
+        let user: User = parse_json_body(&request("application/json; charset=utf-8", r#"{"name": "Ada", "age": 36}"#)).unwrap();
+        assert_eq!(user, User { name: "Ada".to_string(), age: 36 });
+
+        let user: User = parse_json_body(&request("application/vnd.api+json", r#"{"name": "Ada", "age": 36}"#)).unwrap();
+        assert_eq!(user.age, 36);
+    }
+
+    #[test]
+    fn should_reject_invalid_json_with_bad_request() {
+        let err = parse_json_body::<User>(&request("application/json", r#"{"name": "Ada"}"#)).unwrap_err();
+        assert_eq!(err.status_code(), HttpStatusCode::BadRequest);
+
+        let err = parse_json_body::<User>(&request("application/json", "{not json")).unwrap_err();
+        assert_eq!(HttpResponse::from(err).status_code, HttpStatusCode::BadRequest);
+    }
+
+    #[test]
+    fn should_reject_other_media_types_with_unsupported_media_type() {
+        let err = parse_json_body::<User>(&request("text/plain", r#"{"name": "Ada", "age": 36}"#)).unwrap_err();
+        assert_eq!(err.status_code(), HttpStatusCode::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn should_serialize_json_response() {
+        let http_response = HttpResponse::json(&User { name: "Ada".to_string(), age: 36 });
+
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("Content-Type"), Some("application/json"));
+        assert_eq!(http_response.body_bytes(), Some(br#"{"name":"Ada","age":36}"#.as_slice()));
+    }
+}
@@ -0,0 +1,37 @@
+use crate::http_server::http_response::HttpResponse;
+use crate::http_server::lib::RequestContext;
+
+/// Code running around every request handler, registered with `HttpServerBuilder::add_middleware`.
+/// A middleware calls `next.run(cx)` to continue with the rest of the chain, or returns its own response to short-circuit it
+pub trait Middleware: Send + Sync {
+    fn handle(&self, cx: &RequestContext, next: Next) -> HttpResponse;
+}
+
+impl<F: Fn(&RequestContext, Next) -> HttpResponse + Send + Sync> Middleware for F {
+    fn handle(&self, cx: &RequestContext, next: Next) -> HttpResponse {
+        self(cx, next)
+    }
+}
+
+/// The rest of a middleware chain, ending in the request handler
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(&RequestContext) -> HttpResponse
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middlewares: &'a [Box<dyn Middleware>], endpoint: &'a dyn Fn(&RequestContext) -> HttpResponse) -> Self {
+        Next {
+            middlewares,
+            endpoint
+        }
+    }
+
+    /// Runs the remaining middlewares and the request handler, returning their response
+    pub fn run(self, cx: &RequestContext) -> HttpResponse {
+        match self.middlewares.split_first() {
+            Some((middleware, remaining)) => middleware.handle(cx, Next::new(remaining, self.endpoint)),
+            None => (self.endpoint)(cx)
+        }
+    }
+}
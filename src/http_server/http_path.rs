@@ -3,12 +3,32 @@ use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use log::{error, warn};
 use crate::http_server::http_path::PathCompareResult::{Matching, MatchingWithVariables, NotMatching};
+use crate::http_server::http_query::QueryParameters;
+use crate::http_server::helper::percent_decode;
+use crate::http_server::http_error::{HttpServerError, Result};
 
 
+/// Target of a request. The segments are percent-decoded and have their `.` and `..` segments removed,
+/// the target as it was sent is kept in `raw_target`
 #[derive(Debug, Clone)]
 pub struct HttpPath {
+    raw_target: String,
     path: String,
-    pub path_segments: Vec<HttpPathSegment>
+    pub path_segments: Vec<HttpPathSegment>,
+    query: Option<String>,
+    pub query_parameters: QueryParameters,
+    has_invalid_encoding: bool,
+    has_encoded_slash: bool
+}
+
+/// How strictly the percent-encoding of request paths is checked before routing
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PathDecodingPolicy {
+    /// Malformed escapes, invalid UTF-8 and encoded slashes (`%2F`) are answered with 400 Bad Request
+    #[default]
+    Strict,
+    /// Invalid UTF-8 is replaced, malformed escapes are kept as sent and encoded slashes stay part of their segment
+    Lenient
 }
 
 #[derive(Debug, Clone)]
@@ -22,7 +42,9 @@ pub struct RouteMappingPath {
 #[derive(Debug)]
 pub enum RouteMappingPathSegment{
     Constant(String),
-    Variable(String)
+    Variable(String),
+    /// Written as `{*name}`, captures all remaining segments joined with `/`. Only allowed as the last segment
+    Wildcard(String)
 }
 
 #[cfg_attr(
@@ -37,33 +59,51 @@ pub enum PathCompareResult {
 }
 
 impl RouteMappingPath {
-    pub fn matches(&self, http_path: &HttpPath) -> PathCompareResult {
-        type R = RouteMappingPathSegment;
+    /// Ranks how specific this route is, lower ranks take precedence when several routes match a path.
+    /// Segments are compared from left to right, constants beat variables which beat wildcards
+    pub fn specificity(&self) -> Vec<u8> {
+        self.path_segments.iter()
+            .map(|segment| match segment {
+                RouteMappingPathSegment::Constant(_) => 0,
+                RouteMappingPathSegment::Variable(_) => 1,
+                RouteMappingPathSegment::Wildcard(_) => 2
+            })
+            .collect()
+    }
 
+    pub fn matches(&self, http_path: &HttpPath) -> PathCompareResult {
         let route_segments = &self.path_segments;
         let path_segments = &http_path.path_segments;
 
-        if route_segments.len() != path_segments.len() {
+        let has_wildcard = matches!(route_segments.last(), Some(RouteMappingPathSegment::Wildcard(_)));
+        let segment_count_matches = if has_wildcard {
+            path_segments.len() >= route_segments.len() - 1
+        } else {
+            path_segments.len() == route_segments.len()
+        };
+        if !segment_count_matches {
             return NotMatching
         };
 
         let mut variables = HashMap::new();
 
         for (i, segment) in route_segments.iter().enumerate() {
-            let current_segment = &path_segments[i].0;
             match segment {
                 RouteMappingPathSegment::Constant(constant_path) => {
-                    if current_segment != constant_path {
+                    if &path_segments[i].0 != constant_path {
                         return NotMatching
                     };
                 }
                 RouteMappingPathSegment::Variable(name) => {
-                    variables.insert(name.clone(), current_segment.clone());
+                    variables.insert(name.clone(), path_segments[i].0.clone());
+                }
+                RouteMappingPathSegment::Wildcard(name) => {
+                    variables.insert(name.clone(), path_segments[i..].to_path_string());
                 }
             }
         }
 
-        if variables.len() == 0 {
+        if variables.is_empty() {
             Matching
         } else {
             MatchingWithVariables(variables)
@@ -75,24 +115,39 @@ impl From<&str> for RouteMappingPath {
     fn from(value: &str) -> Self {
         let mut variable_names = HashSet::new();
 
-        let path_segments = value.split('/')
+        let path_segments: Vec<RouteMappingPathSegment> = value.split('/')
             .filter(|path_segment| !path_segment.is_empty())
             .map(|path_segment| {
                 if !path_segment.starts_with('{') || !path_segment.ends_with('}') {
                     RouteMappingPathSegment::Constant(path_segment.to_string())
                 } else  {
-                    let name = trim_edges(path_segment).to_string();
+                    let (name, is_wildcard) = match trim_edges(path_segment).strip_prefix('*') {
+                        Some(name) => (name.to_string(), true),
+                        None => (trim_edges(path_segment).to_string(), false)
+                    };
                     if !variable_names.insert(name.clone()) {
                         error!("RouteMappingPath has duplicate path variable name: {}, \"{}\"", value, name);
                     }
-                    if path_segment.len() == 2 {
+                    if name.is_empty() {
                         warn!("RouteMappingPath has empty name for path variable: {}", value);
                     }
-                    RouteMappingPathSegment::Variable(name)
+                    if is_wildcard {
+                        RouteMappingPathSegment::Wildcard(name)
+                    } else {
+                        RouteMappingPathSegment::Variable(name)
+                    }
                 }
             })
             .collect();
 
+        let misplaced_wildcard = path_segments.iter()
+            .rev()
+            .skip(1)
+            .any(|segment| matches!(segment, RouteMappingPathSegment::Wildcard(_)));
+        if misplaced_wildcard {
+            error!("RouteMappingPath has a wildcard that is not the last segment: {}", value);
+        }
+
         RouteMappingPath {
             path: value.to_string(),
             path_segments
@@ -102,38 +157,80 @@ impl From<&str> for RouteMappingPath {
 
 impl From<&str> for HttpPath {
     fn from(value: &str) -> Self {
-        let path_segments = value.split('/')
-            .filter(|path_segment| !path_segment.is_empty())
-            .map(|path_segment| {
-                HttpPathSegment(path_segment.to_string())
-            })
-            .collect();
+        let (path, query) = match value.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (value, None)
+        };
+
+        let mut has_invalid_encoding = false;
+        let mut has_encoded_slash = false;
+        let mut path_segments: Vec<HttpPathSegment> = Vec::new();
+
+        for raw_segment in path.split('/').filter(|path_segment| !path_segment.is_empty()) {
+            let segment = match percent_decode(raw_segment).map(String::from_utf8) {
+                Some(Ok(decoded)) => decoded,
+                Some(Err(err)) => {
+                    has_invalid_encoding = true;
+                    String::from_utf8_lossy(err.as_bytes()).to_string()
+                }
+                None => {
+                    has_invalid_encoding = true;
+                    raw_segment.to_string()
+                }
+            };
+            // The raw segment was split on '/', so any slash left in it was encoded
+            has_encoded_slash |= segment.contains('/');
+
+            // Removes dot segments as described in RFC 3986, section 5.2.4
+            match segment.as_str() {
+                "." => {},
+                ".." => { path_segments.pop(); },
+                _ => path_segments.push(HttpPathSegment(segment))
+            }
+        }
 
         HttpPath {
-            path: value.to_string(),
-            path_segments
+            raw_target: value.to_string(),
+            path: format!("/{}", path_segments.to_path_string()),
+            path_segments,
+            query: query.map(|query| query.to_string()),
+            query_parameters: query.map_or(QueryParameters::default(), |query| query.into()),
+            has_invalid_encoding,
+            has_encoded_slash
         }
     }
 }
 
-impl From<String>for HttpPath {
+impl From<String> for HttpPath {
     fn from(value: String) -> Self {
-        let path_segments = value.split('/')
-            .filter(|path_segment| !path_segment.is_empty())
-            .map(|path_segment| HttpPathSegment(path_segment.to_owned()))
-            .collect();
-
-        HttpPath {
-            path: value,
-            path_segments
-        }
+        value.as_str().into()
     }
 }
 
 impl HttpPath {
+    /// Returns the decoded and normalized path, without the query string
     pub fn as_str(&self) -> &str {
         self.path.as_str()
     }
+
+    /// Returns the request target exactly as it was sent
+    pub fn raw_target(&self) -> &str {
+        self.raw_target.as_str()
+    }
+
+    /// Checks the percent-encoding of the path against the given policy
+    pub fn validate(&self, policy: PathDecodingPolicy) -> Result<()> {
+        match policy {
+            PathDecodingPolicy::Strict if self.has_invalid_encoding => Err(HttpServerError::InvalidPercentEncoding),
+            PathDecodingPolicy::Strict if self.has_encoded_slash => Err(HttpServerError::EncodedSlashInPath),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns the raw query string, without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
 }
 
 pub trait ToPathString {
@@ -212,6 +309,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_match_path_with_query_string() {
+        assert_eq!(
+            RouteMappingPath::from("/echo/{to_echo}").matches(&"/echo/hi?x=1".into()),
+            MatchingWithVariables(HashMap::from([("to_echo".to_string(), "hi".to_string())]))
+        );
+
+        let http_path = HttpPath::from("/echo/hi?x=1&x=2");
+        assert_eq!(http_path.as_str(), "/echo/hi");
+        assert_eq!(http_path.query(), Some("x=1&x=2"));
+        assert_eq!(http_path.query_parameters.get_all("x"), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn should_decode_percent_encoded_segments() {
+        let http_path = HttpPath::from("/echo/hello%20world?x=1");
+
+        assert_eq!(http_path.as_str(), "/echo/hello world");
+        assert_eq!(http_path.raw_target(), "/echo/hello%20world?x=1");
+        assert_eq!(
+            RouteMappingPath::from("/echo/{to_echo}").matches(&http_path),
+            MatchingWithVariables(HashMap::from([("to_echo".to_string(), "hello world".to_string())]))
+        );
+        assert!(http_path.validate(PathDecodingPolicy::Strict).is_ok());
+    }
+
+    #[test]
+    fn should_remove_dot_segments() {
+        assert_eq!(HttpPath::from("/a/../secret").as_str(), "/secret");
+        assert_eq!(HttpPath::from("/a/./b/../../c/").as_str(), "/c");
+        assert_eq!(HttpPath::from("/../../etc/passwd").as_str(), "/etc/passwd");
+        assert_eq!(HttpPath::from("/a/%2e%2E/secret").as_str(), "/secret");
+        assert_eq!(
+            RouteMappingPath::from("/secret").matches(&"/a/../secret".into()),
+            Matching
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_encoding_with_strict_policy() {
+        assert!(matches!(
+            HttpPath::from("/echo/%zz").validate(PathDecodingPolicy::Strict),
+            Err(HttpServerError::InvalidPercentEncoding)
+        ));
+        assert!(matches!(
+            HttpPath::from("/echo/%C3%28").validate(PathDecodingPolicy::Strict),
+            Err(HttpServerError::InvalidPercentEncoding)
+        ));
+        assert!(matches!(
+            HttpPath::from("/echo/a%2Fb").validate(PathDecodingPolicy::Strict),
+            Err(HttpServerError::EncodedSlashInPath)
+        ));
+    }
+
+    #[test]
+    fn should_keep_encoded_slash_in_segment_with_lenient_policy() {
+        let http_path = HttpPath::from("/echo/a%2Fb");
+
+        assert!(http_path.validate(PathDecodingPolicy::Lenient).is_ok());
+        assert_eq!(
+            RouteMappingPath::from("/echo/{to_echo}").matches(&http_path),
+            MatchingWithVariables(HashMap::from([("to_echo".to_string(), "a/b".to_string())]))
+        );
+    }
+
+    #[test]
+    fn should_match_wildcard() {
+        assert_eq!(
+            RouteMappingPath::from("/static/{*rest}").matches(&"/static/css/main.css".into()),
+            MatchingWithVariables(HashMap::from([("rest".to_string(), "css/main.css".to_string())]))
+        );
+        assert_eq!(
+            RouteMappingPath::from("/proxy/{host}/{*rest}").matches(&"/proxy/example/a/b/c".into()),
+            MatchingWithVariables(HashMap::from([
+                ("host".to_string(), "example".to_string()),
+                ("rest".to_string(), "a/b/c".to_string())
+            ]))
+        );
+        assert_eq!(
+            RouteMappingPath::from("/static/{*rest}").matches(&"/static".into()),
+            MatchingWithVariables(HashMap::from([("rest".to_string(), "".to_string())]))
+        );
+        assert_eq!(
+            RouteMappingPath::from("/static/{*rest}").matches(&"/assets/main.css".into()),
+            NotMatching
+        );
+    }
+
+    #[test]
+    fn should_rank_constants_before_variables_before_wildcards() {
+        let mut routes = [
+            RouteMappingPath::from("/users/{*rest}"),
+            RouteMappingPath::from("/users/{id}"),
+            RouteMappingPath::from("/users/me"),
+            RouteMappingPath::from("/{section}/me")
+        ];
+        routes.sort_by_key(RouteMappingPath::specificity);
+
+        assert_eq!(
+            routes.iter().map(|route| route.path.as_str()).collect::<Vec<_>>(),
+            vec!["/users/me", "/users/{id}", "/users/{*rest}", "/{section}/me"]
+        );
+    }
+
     #[test]
     fn should_match_variable() {
         assert_eq!(
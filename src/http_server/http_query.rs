@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use crate::http_server::helper::percent_decode;
+
+/// Parameters from the query string of a request target, a key can have multiple values
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryParameters(HashMap<String, Vec<String>>);
+
+impl QueryParameters {
+
+    /// Returns the first value of a parameter if it exists
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Returns every value of a parameter in the order they appeared in the query string
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.0.get(key)
+            .map_or(Vec::new(), |values| values.iter().map(|value| value.as_str()).collect())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for QueryParameters {
+    fn from(value: &str) -> Self {
+        let mut parameters: HashMap<String, Vec<String>> = HashMap::new();
+
+        value.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .for_each(|(key, value)| {
+                parameters.entry(decode_component(key))
+                    .or_default()
+                    .push(decode_component(value));
+            });
+
+        QueryParameters(parameters)
+    }
+}
+
+/// Decodes a key or value of a query string, where `+` stands for a space.
+/// Malformed escapes are kept as they were sent
+fn decode_component(component: &str) -> String {
+    let component = component.replace('+', " ");
+    percent_decode(&component)
+        .map_or(component, |decoded| String::from_utf8_lossy(&decoded).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_query::*;
+
+    #[test]
+    fn should_parse_query_parameters() {
+        let parameters = QueryParameters::from("x=1&name=tristan");
+
+        assert_eq!(parameters.get("x"), Some("1"));
+        assert_eq!(parameters.get("name"), Some("tristan"));
+        assert_eq!(parameters.get("missing"), None);
+    }
+
+    #[test]
+    fn should_keep_repeated_keys() {
+        let parameters = QueryParameters::from("tag=a&tag=b&other=c&tag=d");
+
+        assert_eq!(parameters.get("tag"), Some("a"));
+        assert_eq!(parameters.get_all("tag"), vec!["a", "b", "d"]);
+    }
+
+    #[test]
+    fn should_parse_empty_values() {
+        let parameters = QueryParameters::from("flag&empty=&&x=1");
+
+        assert_eq!(parameters.get("flag"), Some(""));
+        assert_eq!(parameters.get("empty"), Some(""));
+        assert_eq!(parameters.get("x"), Some("1"));
+    }
+
+    #[test]
+    fn should_decode_plus_as_space() {
+        let parameters = QueryParameters::from("greeting=hello+world&a+b=c");
+
+        assert_eq!(parameters.get("greeting"), Some("hello world"));
+        assert_eq!(parameters.get("a b"), Some("c"));
+    }
+
+    #[test]
+    fn should_percent_decode_parameters() {
+        let parameters = QueryParameters::from("q=a%26b%3Dc&plus=1%2B1&broken=%zz");
+
+        assert_eq!(parameters.get("q"), Some("a&b=c"));
+        assert_eq!(parameters.get("plus"), Some("1+1"));
+        assert_eq!(parameters.get("broken"), Some("%zz"));
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use std::io::{Cursor, Read, Seek, SeekFrom, Take};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::error;
+use crate::http_server::http_body::HttpBody;
+use crate::http_server::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
+
+/// Requests with more ranges than this are answered with the whole content
+const MAX_RANGES: usize = 16;
+/// Ends the content of every part of a multipart body
+const PART_END: &[u8] = b"\r\n";
+
+/// A single range of a `Range: bytes=...` header, with inclusive positions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// `first-last`
+    FromTo(u64, u64),
+    /// `first-`, everything from `first` to the end
+    From(u64),
+    /// `-length`, the last `length` bytes
+    Suffix(u64)
+}
+
+impl ByteRange {
+    /// Resolves the range against content of the given length, returning the first and last position.
+    /// Returns None if the range is not satisfiable
+    pub fn resolve(&self, length: u64) -> Option<(u64, u64)> {
+        if length == 0 {
+            return None
+        }
+
+        match *self {
+            ByteRange::FromTo(first, last) if first < length => Some((first, last.min(length - 1))),
+            ByteRange::From(first) if first < length => Some((first, length - 1)),
+            ByteRange::Suffix(suffix_length) if suffix_length > 0 => Some((length - suffix_length.min(length), length - 1)),
+            _ => None
+        }
+    }
+}
+
+/// Parses the value of a `Range` header. Returns None if it is malformed or not in bytes,
+/// in which case the header is ignored
+pub fn parse_range_header(value: &str) -> Option<Vec<ByteRange>> {
+    let (unit, ranges) = value.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None
+    }
+
+    ranges.split(',')
+        .map(|range| range.trim())
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let (first, last) = range.split_once('-')?;
+            match (first.trim(), last.trim()) {
+                ("", "") => None,
+                ("", suffix_length) => Some(ByteRange::Suffix(suffix_length.parse().ok()?)),
+                (first, "") => Some(ByteRange::From(first.parse().ok()?)),
+                (first, last) => {
+                    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                    if first > last { None } else { Some(ByteRange::FromTo(first, last)) }
+                }
+            }
+        })
+        .collect::<Option<Vec<ByteRange>>>()
+        .filter(|ranges| !ranges.is_empty())
+}
+
+/// Answers a GET request with the requested ranges of `content`: 206 Partial Content for satisfiable ranges,
+/// using `multipart/byteranges` if there are several, or 416 Range Not Satisfiable.
+/// Without a usable `Range` header the whole content is sent with 200 OK
+pub fn ranged_response(http_request: &HttpRequest, content: &[u8], content_type: &str) -> HttpResponse {
+    let length = content.len() as u64;
+    let slice = |(first, last): (u64, u64)| &content[first as usize..=last as usize];
+
+    match select_ranges(http_request, length) {
+        RangeSelection::Full => full_response(content_type, content.to_vec()),
+        RangeSelection::Unsatisfiable => unsatisfiable_response(length),
+        RangeSelection::Single(range) => single_range_response(content_type, range, length, slice(range).to_vec()),
+        RangeSelection::Multiple(ranges) => {
+            let boundary = generate_boundary();
+            let mut body = Vec::new();
+            for range in ranges {
+                body.extend_from_slice(part_header(&boundary, content_type, range, length).as_bytes());
+                body.extend_from_slice(slice(range));
+                body.extend_from_slice(PART_END);
+            }
+            body.extend_from_slice(closing_delimiter(&boundary).as_bytes());
+
+            multipart_response(&boundary, body)
+        }
+    }
+}
+
+/// Same as `ranged_response`, but streams the `length` bytes of `reader` instead of holding them in memory.
+/// Each range is read by seeking to its start and taking as many bytes as it spans
+pub fn ranged_reader_response<R: Read + Seek + Send + 'static>(http_request: &HttpRequest, mut reader: R, length: u64, content_type: &str) -> HttpResponse {
+    match select_ranges(http_request, length) {
+        RangeSelection::Full => match reader.rewind() {
+            Ok(()) => full_response(content_type, HttpBody::from_reader(reader, Some(length))),
+            Err(err) => seek_failed_response(err)
+        },
+        RangeSelection::Unsatisfiable => unsatisfiable_response(length),
+        RangeSelection::Single((first, last)) => match reader.seek(SeekFrom::Start(first)) {
+            Ok(_) => {
+                let body = HttpBody::from_reader(reader.take(last - first + 1), Some(last - first + 1));
+                single_range_response(content_type, (first, last), length, body)
+            }
+            Err(err) => seek_failed_response(err)
+        },
+        RangeSelection::Multiple(ranges) => {
+            let boundary = generate_boundary();
+            let mut parts = VecDeque::new();
+            for range in ranges {
+                parts.push_back(MultipartPart::Text(part_header(&boundary, content_type, range, length).into_bytes()));
+                parts.push_back(MultipartPart::Range(range));
+                parts.push_back(MultipartPart::Text(PART_END.to_vec()));
+            }
+            parts.push_back(MultipartPart::Text(closing_delimiter(&boundary).into_bytes()));
+
+            let body_length = parts.iter().map(MultipartPart::len).sum();
+            let multipart_reader = MultipartReader { reader: reader.take(0), text: Cursor::new(Vec::new()), parts };
+            multipart_response(&boundary, HttpBody::from_reader(multipart_reader, Some(body_length)))
+        }
+    }
+}
+
+/// What a request asks for, after resolving its `Range` header against the length of the content
+enum RangeSelection {
+    Full,
+    Unsatisfiable,
+    Single((u64, u64)),
+    Multiple(Vec<(u64, u64)>)
+}
+
+fn select_ranges(http_request: &HttpRequest, length: u64) -> RangeSelection {
+    let method = &http_request.request_line.method;
+    if method != &HttpMethod::GET && method != &HttpMethod::HEAD {
+        return RangeSelection::Full
+    }
+    let ranges = match http_request.headers.get("Range").and_then(parse_range_header) {
+        Some(ranges) if ranges.len() <= MAX_RANGES => ranges,
+        _ => return RangeSelection::Full
+    };
+
+    let satisfiable_ranges: Vec<(u64, u64)> = ranges.iter()
+        .filter_map(|range| range.resolve(length))
+        .collect();
+
+    match satisfiable_ranges.as_slice() {
+        [] => RangeSelection::Unsatisfiable,
+        [range] => RangeSelection::Single(*range),
+        _ => RangeSelection::Multiple(satisfiable_ranges)
+    }
+}
+
+fn full_response<B: Into<HttpBody>>(content_type: &str, body: B) -> HttpResponse {
+    HttpResponse::builder()
+        .status_code(HttpStatusCode::Ok)
+        .add_header(("Content-Type", content_type))
+        .add_header(("Accept-Ranges", "bytes"))
+        .body(body)
+        .build()
+}
+
+fn unsatisfiable_response(length: u64) -> HttpResponse {
+    HttpResponse::builder()
+        .status_code(HttpStatusCode::RangeNotSatisfiable)
+        .add_header(("Content-Range", &format!("bytes */{}", length)))
+        .build()
+}
+
+fn single_range_response<B: Into<HttpBody>>(content_type: &str, (first, last): (u64, u64), length: u64, body: B) -> HttpResponse {
+    HttpResponse::builder()
+        .status_code(HttpStatusCode::PartialContent)
+        .add_header(("Content-Type", content_type))
+        .add_header(("Accept-Ranges", "bytes"))
+        .add_header(("Content-Range", &format!("bytes {}-{}/{}", first, last, length)))
+        .body(body)
+        .build()
+}
+
+fn multipart_response<B: Into<HttpBody>>(boundary: &str, body: B) -> HttpResponse {
+    HttpResponse::builder()
+        .status_code(HttpStatusCode::PartialContent)
+        .add_header(("Content-Type", &format!("multipart/byteranges; boundary={}", boundary)))
+        .add_header(("Accept-Ranges", "bytes"))
+        .body(body)
+        .build()
+}
+
+fn seek_failed_response(err: std::io::Error) -> HttpResponse {
+    error!("Failed to seek in ranged content: {}", err);
+    HttpStatusCode::InternalServerError.into()
+}
+
+fn part_header(boundary: &str, content_type: &str, (first, last): (u64, u64), length: u64) -> String {
+    format!("--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", boundary, content_type, first, last, length)
+}
+
+fn closing_delimiter(boundary: &str) -> String {
+    format!("--{}--\r\n", boundary)
+}
+
+/// A piece of a streamed `multipart/byteranges` body
+enum MultipartPart {
+    Text(Vec<u8>),
+    Range((u64, u64))
+}
+
+impl MultipartPart {
+    fn len(&self) -> u64 {
+        match self {
+            MultipartPart::Text(text) => text.len() as u64,
+            MultipartPart::Range((first, last)) => last - first + 1
+        }
+    }
+}
+
+/// Reads the parts of a `multipart/byteranges` body one after another, seeking to each range when it is reached
+struct MultipartReader<R: Read + Seek> {
+    reader: Take<R>,
+    text: Cursor<Vec<u8>>,
+    parts: VecDeque<MultipartPart>
+}
+
+impl<R: Read + Seek> Read for MultipartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.text.read(buf)?;
+            if read > 0 {
+                return Ok(read)
+            }
+            if self.reader.limit() > 0 {
+                return match self.reader.read(buf)? {
+                    0 => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Ranged content ended before the range")),
+                    read => Ok(read)
+                }
+            }
+
+            match self.parts.pop_front() {
+                Some(MultipartPart::Text(text)) => self.text = Cursor::new(text),
+                Some(MultipartPart::Range((first, last))) => {
+                    self.reader.get_mut().seek(SeekFrom::Start(first))?;
+                    self.reader.set_limit(last - first + 1);
+                }
+                None => return Ok(0)
+            }
+        }
+    }
+}
+
+/// Creates a multipart boundary that is unlikely to appear in the content
+fn generate_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.subsec_nanos());
+
+    format!("byteranges_{:08x}{:08x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_range::*;
+
+    const CONTENT: &[u8] = b"0123456789abcdefghij";
+
+    fn request_with_range(range: &str) -> HttpRequest {
+        HttpRequest::from_bytes(format!("GET /file HTTP/1.1\r\nRange: {}\r\n\r\n", range).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn should_parse_range_header() {
+        assert_eq!(parse_range_header("bytes=0-99"), Some(vec![ByteRange::FromTo(0, 99)]));
+        assert_eq!(
+            parse_range_header("bytes=0-4, 10-, -5"),
+            Some(vec![ByteRange::FromTo(0, 4), ByteRange::From(10), ByteRange::Suffix(5)])
+        );
+        assert_eq!(parse_range_header("bytes=5-1"), None);
+        assert_eq!(parse_range_header("bytes=-"), None);
+        assert_eq!(parse_range_header("items=0-4"), None);
+        assert_eq!(parse_range_header("bytes=a-b"), None);
+    }
+
+    #[test]
+    fn should_resolve_ranges_against_length() {
+        assert_eq!(ByteRange::FromTo(0, 99).resolve(20), Some((0, 19)));
+        assert_eq!(ByteRange::From(15).resolve(20), Some((15, 19)));
+        assert_eq!(ByteRange::Suffix(5).resolve(20), Some((15, 19)));
+        assert_eq!(ByteRange::Suffix(50).resolve(20), Some((0, 19)));
+        assert_eq!(ByteRange::From(20).resolve(20), None);
+        assert_eq!(ByteRange::Suffix(0).resolve(20), None);
+    }
+
+    #[test]
+    fn should_answer_single_range_with_partial_content() {
+        let http_response = ranged_response(&request_with_range("bytes=2-5"), CONTENT, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::PartialContent);
+        assert_eq!(http_response.headers.get("Content-Range"), Some("bytes 2-5/20"));
+        assert_eq!(http_response.headers.get("Content-Length"), Some("4"));
+        assert_eq!(http_response.body_bytes(), Some(b"2345".as_slice()));
+    }
+
+    #[test]
+    fn should_answer_suffix_range() {
+        let http_response = ranged_response(&request_with_range("bytes=-3"), CONTENT, "text/plain");
+
+        assert_eq!(http_response.headers.get("Content-Range"), Some("bytes 17-19/20"));
+        assert_eq!(http_response.body_bytes(), Some(b"hij".as_slice()));
+    }
+
+    #[test]
+    fn should_answer_multiple_ranges_with_multipart_body() {
+        let http_response = ranged_response(&request_with_range("bytes=0-1,-2"), CONTENT, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::PartialContent);
+        let content_type = http_response.headers.get("Content-Type").unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(http_response.body_bytes().unwrap()),
+            format!(
+                "--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/20\r\n\r\n01\r\n\
+                --{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 18-19/20\r\n\r\nij\r\n\
+                --{0}--\r\n",
+                boundary
+            )
+        );
+    }
+
+    fn streamed_body(http_response: HttpResponse) -> Vec<u8> {
+        let mut body = Vec::new();
+        http_response.body.unwrap().write_to(&mut body, false).unwrap();
+        body
+    }
+
+    #[test]
+    fn should_stream_single_range_from_reader() {
+        let http_response = ranged_reader_response(&request_with_range("bytes=2-5"), Cursor::new(CONTENT), CONTENT.len() as u64, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::PartialContent);
+        assert_eq!(http_response.headers.get("Content-Range"), Some("bytes 2-5/20"));
+        assert_eq!(http_response.headers.get("Content-Length"), Some("4"));
+        assert_eq!(streamed_body(http_response), b"2345");
+    }
+
+    #[test]
+    fn should_stream_multiple_ranges_from_reader() {
+        let http_request = request_with_range("bytes=0-1,-2");
+        let expected = ranged_response(&http_request, CONTENT, "text/plain");
+        let expected_body = String::from_utf8_lossy(expected.body_bytes().unwrap()).to_string();
+
+        let http_response = ranged_reader_response(&http_request, Cursor::new(CONTENT), CONTENT.len() as u64, "text/plain");
+        let content_type = http_response.headers.get("Content-Type").unwrap().to_string();
+        let content_length = http_response.headers.get("Content-Length").unwrap().to_string();
+        let body = String::from_utf8(streamed_body(http_response)).unwrap();
+
+        // Both bodies only differ in their boundaries, which have the same length
+        let expected_boundary = expected.headers.get("Content-Type").unwrap().strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        assert_eq!(body, expected_body.replace(expected_boundary, boundary));
+        assert_eq!(content_length, body.len().to_string());
+    }
+
+    #[test]
+    fn should_stream_whole_reader_without_range() {
+        let mut reader = Cursor::new(CONTENT);
+        reader.set_position(5);
+        let http_request = HttpRequest::from_bytes(b"GET /file HTTP/1.1\r\n\r\n").unwrap();
+        let http_response = ranged_reader_response(&http_request, reader, CONTENT.len() as u64, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("Content-Length"), Some("20"));
+        assert_eq!(streamed_body(http_response), CONTENT);
+    }
+
+    #[test]
+    fn should_answer_unsatisfiable_range() {
+        let http_response = ranged_response(&request_with_range("bytes=50-60"), CONTENT, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::RangeNotSatisfiable);
+        assert_eq!(http_response.headers.get("Content-Range"), Some("bytes */20"));
+    }
+
+    #[test]
+    fn should_ignore_invalid_range() {
+        let http_response = ranged_response(&request_with_range("bytes=x-y"), CONTENT, "text/plain");
+
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.body_bytes(), Some(CONTENT));
+    }
+}
@@ -0,0 +1,341 @@
+use std::io::{ErrorKind, Read};
+use crate::http_server::http_error::{HttpServerError, Result};
+use crate::http_server::http_request::{HttpRequest, HttpRequestHeader};
+
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+const LINE_TERMINATOR: &[u8] = b"\r\n";
+const READ_CHUNK_SIZE: usize = 1024;
+/// Size of the pieces a body is read in, so memory only grows as bytes actually arrive
+const BODY_READ_CHUNK_SIZE: usize = 8192;
+/// Upper bound for a single chunk-size or trailer line of a chunked body
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Reads HTTP messages off a stream, keeping any bytes read past the current message in a buffer
+pub struct HttpReader<R: Read> {
+    inner: R,
+    buffer: Vec<u8>
+}
+
+impl<R: Read> HttpReader<R> {
+    pub fn new(inner: R) -> Self {
+        HttpReader {
+            inner,
+            buffer: Vec::new()
+        }
+    }
+
+    /// Reads the request line and headers, leaving anything after the empty line in the buffer.
+    /// Keeps reading until the empty line is found or the head grows past `max_head_size` bytes
+    pub fn read_head(&mut self, max_head_size: usize) -> Result<Vec<u8>> {
+        let mut search_start = 0;
+
+        loop {
+            let terminator_position = self.buffer[search_start..].windows(HEAD_TERMINATOR.len())
+                .position(|window| window == HEAD_TERMINATOR)
+                .map(|pos| search_start + pos);
+
+            if let Some(pos) = terminator_position {
+                let head_length = pos + HEAD_TERMINATOR.len();
+                if head_length > max_head_size {
+                    return Err(HttpServerError::HeaderTooLarge)
+                }
+                return Ok(self.buffer.drain(..head_length).collect())
+            }
+            if self.buffer.len() >= max_head_size {
+                return Err(HttpServerError::HeaderTooLarge)
+            }
+
+            // The terminator might be split between the current buffer and the next read
+            search_start = self.buffer.len().saturating_sub(HEAD_TERMINATOR.len() - 1);
+            if self.fill_buffer()? == 0 {
+                return if self.buffer.is_empty() {
+                    Err(HttpServerError::ConnectionClosed)
+                } else {
+                    Err(HttpServerError::InvalidHttpRequestStructure)
+                }
+            }
+        }
+    }
+
+    /// Reads the body following a request head into `http_request`, framed either by
+    /// `Transfer-Encoding: chunked` or by the `Content-Length` header.
+    /// Bodies larger than `max_body_size` bytes fail with `BodyTooLarge`, trailer sections larger than
+    /// `max_header_size` bytes with `HeaderTooLarge`
+    pub fn read_body(&mut self, http_request: &mut HttpRequest, max_body_size: usize, max_header_size: usize) -> Result<()> {
+        if let Some(transfer_encoding) = http_request.headers.get_joined("Transfer-Encoding") {
+            if !transfer_encoding.trim().eq_ignore_ascii_case("chunked") {
+                return Err(HttpServerError::UnsupportedTransferEncoding)
+            }
+            let (body, trailers) = self.read_chunked_body(max_body_size, max_header_size)
+                .map_err(|err| map_unexpected_eof(err, HttpServerError::InvalidChunkedEncoding))?;
+            http_request.body = body;
+            http_request.trailers = trailers;
+            return Ok(())
+        }
+
+        let content_lengths = http_request.headers.get_all("Content-Length");
+        let content_length = match content_lengths.first() {
+            None => return Ok(()),
+            // Differing lengths make the end of the body ambiguous
+            Some(value) if content_lengths.iter().any(|other| other != value) => return Err(HttpServerError::InvalidContentLength),
+            Some(value) => value.parse::<u64>().map_err(|_| HttpServerError::InvalidContentLength)?
+        };
+        if content_length > max_body_size as u64 {
+            return Err(HttpServerError::BodyTooLarge)
+        }
+        let content_length = content_length as usize;
+
+        http_request.body = self.read_exact_bytes(content_length)
+            .map_err(|err| map_unexpected_eof(err, HttpServerError::BodyLengthMismatch))?;
+        Ok(())
+    }
+
+    /// Decodes a chunked body, ignoring chunk extensions and collecting the trailer section.
+    /// Fails with `BodyTooLarge` as soon as a chunk would grow the body past `max_body_size`,
+    /// and with `HeaderTooLarge` once the trailer section grows past `max_header_size`
+    fn read_chunked_body(&mut self, max_body_size: usize, max_header_size: usize) -> Result<(Vec<u8>, HttpRequestHeader)> {
+        let mut body = Vec::new();
+
+        loop {
+            let chunk_size = parse_chunk_size(&self.read_line()?)?;
+            if chunk_size == 0 { break }
+            if chunk_size > max_body_size - body.len() {
+                return Err(HttpServerError::BodyTooLarge)
+            }
+
+            body.extend(self.read_exact_bytes(chunk_size)?);
+            if !self.read_line()?.is_empty() { return Err(HttpServerError::InvalidChunkedEncoding) }
+        }
+
+        let mut trailer_lines = Vec::new();
+        let mut trailer_size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() { break }
+            trailer_size += line.len() + LINE_TERMINATOR.len();
+            if trailer_size > max_header_size {
+                return Err(HttpServerError::HeaderTooLarge)
+            }
+            trailer_lines.push(line);
+        }
+        let trailer_lines: Vec<&[u8]> = trailer_lines.iter().map(Vec::as_slice).collect();
+
+        Ok((body, HttpRequestHeader::from_bytes(&trailer_lines)?))
+    }
+
+    /// Reads a single CRLF terminated line, without the terminator
+    fn read_line(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(pos) = self.buffer.windows(LINE_TERMINATOR.len()).position(|window| window == LINE_TERMINATOR) {
+                let line = self.buffer[..pos].to_vec();
+                self.buffer.drain(..pos + LINE_TERMINATOR.len());
+                return Ok(line)
+            }
+            if self.buffer.len() > MAX_LINE_LENGTH {
+                return Err(HttpServerError::InvalidChunkedEncoding)
+            }
+            if self.fill_buffer()? == 0 {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into())
+            }
+        }
+    }
+
+    /// Reads whatever is available on the stream into the buffer, returning the amount of bytes read
+    fn fill_buffer(&mut self) -> Result<usize> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        let read_bytes = self.inner.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read_bytes]);
+        Ok(read_bytes)
+    }
+
+    /// Reads exactly `length` bytes, taking buffered bytes first and the rest from the stream.
+    /// The bytes are read in bounded pieces, a length announced by the client is never allocated up front
+    pub fn read_exact_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        let buffered = length.min(self.buffer.len());
+        let mut bytes: Vec<u8> = self.buffer.drain(..buffered).collect();
+
+        let mut chunk = [0; BODY_READ_CHUNK_SIZE];
+        while bytes.len() < length {
+            let wanted = (length - bytes.len()).min(chunk.len());
+            match self.inner.read(&mut chunk[..wanted]) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(read_bytes) => bytes.extend_from_slice(&chunk[..read_bytes]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into())
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Parses the hexadecimal size at the start of a chunk-size line, ignoring any chunk extensions
+fn parse_chunk_size(line: &[u8]) -> Result<usize> {
+    let line = String::from_utf8_lossy(line);
+    let size = line.split(';').next().unwrap_or_default().trim();
+
+    if size.is_empty() || !size.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err(HttpServerError::InvalidChunkedEncoding)
+    }
+    usize::from_str_radix(size, 16).map_err(|_| HttpServerError::InvalidChunkedEncoding)
+}
+
+/// Replaces an unexpected end of stream with a more specific error
+fn map_unexpected_eof(err: HttpServerError, replacement: HttpServerError) -> HttpServerError {
+    match err {
+        HttpServerError::IoError(io_err) if io_err.kind() == ErrorKind::UnexpectedEof => replacement,
+        err => err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_reader::*;
+    use crate::http_server::http_request::HttpRequest;
+
+    const MAX_HEAD_SIZE: usize = 8 * 1024;
+    const MAX_BODY_SIZE: usize = 64 * 1024;
+
+    fn read_request(raw: &[u8]) -> Result<HttpRequest> {
+        let mut reader = HttpReader::new(raw);
+        let mut http_request = HttpRequest::from_bytes(&reader.read_head(MAX_HEAD_SIZE)?)?;
+        reader.read_body(&mut http_request, MAX_BODY_SIZE, MAX_HEAD_SIZE)?;
+        Ok(http_request)
+    }
+
+    #[test]
+    fn should_read_head_across_multiple_reads() {
+        let user_agent = "a".repeat(3000);
+        let raw = format!("GET /user-agent HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nAccept: */*\r\n\r\n", user_agent);
+        let http_request = read_request(raw.as_bytes()).unwrap();
+
+        assert_eq!(http_request.headers.get("User-Agent"), Some(user_agent.as_str()));
+        assert_eq!(http_request.headers.get("Accept"), Some("*/*"));
+    }
+
+    #[test]
+    fn should_fail_when_head_exceeds_max_size() {
+        let raw = format!("GET / HTTP/1.1\r\nUser-Agent: {}\r\n\r\n", "a".repeat(100));
+        let mut reader = HttpReader::new(raw.as_bytes());
+
+        assert!(matches!(reader.read_head(64), Err(HttpServerError::HeaderTooLarge)));
+    }
+
+    #[test]
+    fn should_fail_when_stream_ends_before_head() {
+        let mut reader = HttpReader::new(b"GET / HTTP/1.1\r\nHost: loc".as_slice());
+
+        assert!(matches!(reader.read_head(MAX_HEAD_SIZE), Err(HttpServerError::InvalidHttpRequestStructure)));
+    }
+
+    #[test]
+    fn should_read_body_by_content_length() {
+        let http_request = read_request(b"POST /data HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+
+        assert_eq!(http_request.body, b"hello");
+        assert_eq!(http_request.headers.get("Host"), Some("localhost"));
+    }
+
+    #[test]
+    fn should_read_empty_body_without_content_length() {
+        let http_request = read_request(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        assert!(http_request.body.is_empty());
+    }
+
+    #[test]
+    fn should_fail_when_body_is_shorter_than_content_length() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello");
+
+        assert!(matches!(result, Err(HttpServerError::BodyLengthMismatch)));
+    }
+
+    #[test]
+    fn should_fail_on_invalid_content_length() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nContent-Length: five\r\n\r\nhello");
+
+        assert!(matches!(result, Err(HttpServerError::InvalidContentLength)));
+    }
+
+    #[test]
+    fn should_fail_when_content_length_exceeds_max_body_size() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nContent-Length: 1099511627776\r\n\r\nx");
+
+        assert!(matches!(result, Err(HttpServerError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn should_read_body_larger_than_one_read() {
+        let body = "a".repeat(20_000);
+        let raw = format!("POST /data HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let http_request = read_request(raw.as_bytes()).unwrap();
+
+        assert_eq!(http_request.body, body.as_bytes());
+    }
+
+    #[test]
+    fn should_fail_on_conflicting_content_lengths() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 6\r\n\r\nhello!");
+
+        assert!(matches!(result, Err(HttpServerError::InvalidContentLength)));
+    }
+
+    #[test]
+    fn should_decode_chunked_body() {
+        let http_request = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\n\r\n").unwrap();
+
+        assert_eq!(http_request.body, b"hello, world");
+    }
+
+    #[test]
+    fn should_read_chunked_trailers() {
+        let http_request = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\nChecksum: 1234\r\n\r\n").unwrap();
+
+        assert_eq!(http_request.body, b"abc");
+        assert_eq!(http_request.trailers.get("Checksum"), Some("1234"));
+    }
+
+    #[test]
+    fn should_fail_when_chunk_exceeds_max_body_size() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFF\r\nabc");
+
+        assert!(matches!(result, Err(HttpServerError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn should_fail_when_chunks_together_exceed_max_body_size() {
+        let chunk = "a".repeat(40 * 1024);
+        let raw = format!("POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{0:X}\r\n{1}\r\n{0:X}\r\n{1}\r\n0\r\n\r\n", chunk.len(), chunk);
+
+        assert!(matches!(read_request(raw.as_bytes()), Err(HttpServerError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn should_fail_when_trailers_exceed_max_header_size() {
+        let trailers = "X-Trailer: value\r\n".repeat(MAX_HEAD_SIZE);
+        let raw = format!("POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n{}\r\n", trailers);
+
+        assert!(matches!(read_request(raw.as_bytes()), Err(HttpServerError::HeaderTooLarge)));
+    }
+
+    #[test]
+    fn should_fail_on_invalid_chunk_size() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nabc\r\n0\r\n\r\n");
+
+        assert!(matches!(result, Err(HttpServerError::InvalidChunkedEncoding)));
+    }
+
+    #[test]
+    fn should_fail_on_truncated_chunked_body() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\na\r\nabc");
+
+        assert!(matches!(result, Err(HttpServerError::InvalidChunkedEncoding)));
+    }
+
+    #[test]
+    fn should_reject_unsupported_transfer_encoding() {
+        let result = read_request(b"POST /data HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n");
+
+        assert!(matches!(result, Err(HttpServerError::UnsupportedTransferEncoding)));
+    }
+}
@@ -1,18 +1,21 @@
-use std::collections::HashMap;
-use crate::create_enum_and_matchers;
 use crate::http_server::helper::{split_lines_by_byte, split_lines_by_bytes};
 use crate::http_server::http_path::HttpPath;
 use crate::http_server::http_version::HttpVersion;
 use crate::http_server::HttpServerError;
 use crate::http_server::http_error::Result;
 
-#[derive(Debug)]
-pub struct HttpRequestHeader(HashMap<String, String>);
+/// Request header fields in the order they were received.
+/// Lookups ignore the case of the name and repeated fields keep all of their values
+#[derive(Debug, Default)]
+pub struct HttpRequestHeader(Vec<(String, String)>);
 
 #[derive(Debug)]
 pub struct HttpRequest {
     pub request_line: RequestLine,
-    pub headers: HttpRequestHeader
+    pub headers: HttpRequestHeader,
+    pub body: Vec<u8>,
+    /// Trailer fields sent after a chunked body
+    pub trailers: HttpRequestHeader
 }
 
 /// The first line of a HttpRequest
@@ -30,26 +33,103 @@ const COLON: u8 = b':';
 
 impl HttpRequest {
 
-    /// Takes in the bytes received from a TcpStream and trys to convert them into a HttpRequest
+    /// Takes in the head bytes received from a TcpStream and trys to convert them into a HttpRequest.
+    /// The body and trailers are left empty, they are read separately according to the headers
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         let split_lines = split_lines_by_bytes(bytes, &[CARRIAGE_RETURN, NEWLINE]);
-        let http_lines = split_lines.len();
         let request_line = RequestLine::from_bytes(split_lines.first().ok_or(HttpServerError::InvalidRequestLineSyntax)?)?;
-        let headers = HttpRequestHeader::from_bytes(&split_lines[1..http_lines-2]);
+        let header_lines: Vec<&[u8]> = split_lines.iter()
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .copied()
+            .collect();
+        let headers = HttpRequestHeader::from_bytes(&header_lines)?;
 
         Ok(Self {
             request_line,
-            headers
+            headers,
+            body: Vec::new(),
+            trailers: HttpRequestHeader::default()
         })
     }
+
+    /// Returns true if the connection should stay open after this request.
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent, older versions have to ask for `keep-alive`
+    pub fn is_keep_alive(&self) -> bool {
+        if self.has_connection_option("close") {
+            false
+        } else if self.request_line.http_version.is_at_least(1, 1) {
+            true
+        } else {
+            self.has_connection_option("keep-alive")
+        }
+    }
+
+    /// Returns true if the request carries both `Transfer-Encoding` and `Content-Length`. The body is framed by
+    /// Transfer-Encoding, but the connection has to be closed afterwards as intermediaries may disagree on where
+    /// the request ends, see RFC 9112, section 6.1
+    pub fn has_conflicting_framing(&self) -> bool {
+        self.headers.contains_key("Transfer-Encoding") && self.headers.contains_key("Content-Length")
+    }
+
+    fn has_connection_option(&self, option: &str) -> bool {
+        self.headers.get_joined("Connection")
+            .is_some_and(|value| value.split(',').any(|token| token.trim().eq_ignore_ascii_case(option)))
+    }
 }
 
 impl HttpRequestHeader {
 
-    /// Returns the value of a header if it exists
+    /// Returns the first value of a header if it exists
     pub fn get(&self, header_key: &str) -> Option<&str> {
-        self.0.get(header_key)
-            .map(|value| value.as_str())
+        self.get_all(header_key).into_iter().next()
+    }
+
+    /// Returns every value of a header in the order they were received
+    pub fn get_all(&self, header_key: &str) -> Vec<&str> {
+        self.0.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(header_key))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Returns all values of a list header joined with commas, as if they were sent on a single line
+    pub fn get_joined(&self, header_key: &str) -> Option<String> {
+        let values = self.get_all(header_key);
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    pub fn contains_key(&self, header_key: &str) -> bool {
+        self.0.iter().any(|(key, _)| key.eq_ignore_ascii_case(header_key))
+    }
+
+    /// Adds a header, keeping any values already present for the same name
+    pub fn append(&mut self, header_key: &str, value: &str) {
+        self.0.push((header_key.to_owned(), value.to_owned()));
+    }
+
+    /// Removes every value of a header, returning true if there was any
+    pub fn remove(&mut self, header_key: &str) -> bool {
+        let length = self.0.len();
+        self.0.retain(|(key, _)| !key.eq_ignore_ascii_case(header_key));
+        self.0.len() != length
+    }
+
+    /// Iterates over every header as it was received, including repeated names
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
@@ -74,24 +154,169 @@ impl RequestLine {
 
 impl HttpRequestHeader {
 
-    /// Takes in a slice of bytes and converts them into a HashMap of headers
-    pub fn from_bytes(line_bytes: &[&[u8]]) -> Self {
+    /// Takes in a slice of bytes and converts them into a list of headers.
+    /// Fails if a line has no colon or its field name is not a token, which includes whitespace before the colon
+    /// (RFC 9112, section 5.1). Such names would otherwise be matched differently than by other servers on the path
+    pub fn from_bytes(line_bytes: &[&[u8]]) -> Result<Self> {
 
-        let lines: HashMap<String, String> = line_bytes.iter()
-            .map(|bytes| String::from_utf8_lossy(bytes).to_string())
-            .filter_map(|header_line| {
-                header_line.split_once(':')
-                    .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+        let lines: Vec<(String, String)> = line_bytes.iter()
+            .map(|bytes| {
+                let colon = bytes.iter().position(|byte| *byte == b':').ok_or(HttpServerError::InvalidHeaderSyntax)?;
+                let (name, value) = (&bytes[..colon], &bytes[colon + 1..]);
+                if !is_token(name) {
+                    return Err(HttpServerError::InvalidHeaderSyntax)
+                }
+                Ok((String::from_utf8_lossy(name).to_string(), String::from_utf8_lossy(value).trim().to_string()))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
-        Self(
+        Ok(Self(
             lines
-        )
+        ))
     }
 }
 
 
-create_enum_and_matchers!(HttpMethod, GET, POST, PUT, DELETE, OPTION, HEAD);
+/// Request methods from RFC 9110 and RFC 5789 (PATCH), other method tokens are kept as extension methods
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub enum HttpMethod {
+    #[default]
+    GET,
+    HEAD,
+    POST,
+    PUT,
+    DELETE,
+    CONNECT,
+    OPTIONS,
+    TRACE,
+    PATCH,
+    /// Any other method token, for example `PROPFIND` from WebDAV. Methods are case-sensitive
+    Extension(String)
+}
 
+impl HttpMethod {
+    /// Takes in the method of a request line, returning None if it is not a valid token
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let method = match bytes {
+            b"GET" => HttpMethod::GET,
+            b"HEAD" => HttpMethod::HEAD,
+            b"POST" => HttpMethod::POST,
+            b"PUT" => HttpMethod::PUT,
+            b"DELETE" => HttpMethod::DELETE,
+            b"CONNECT" => HttpMethod::CONNECT,
+            b"OPTIONS" => HttpMethod::OPTIONS,
+            b"TRACE" => HttpMethod::TRACE,
+            b"PATCH" => HttpMethod::PATCH,
+            bytes if is_token(bytes) => HttpMethod::Extension(String::from_utf8_lossy(bytes).to_string()),
+            _ => return None
+        };
+        Some(method)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::Extension(method) => method
+        }
+    }
+}
 
+impl From<&str> for HttpMethod {
+    /// Converts a method name into a HttpMethod, names without a variant become extension methods
+    fn from(value: &str) -> Self {
+        HttpMethod::from_bytes(value.as_bytes())
+            .unwrap_or(HttpMethod::Extension(value.to_string()))
+    }
+}
+
+impl std::fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Checks that the bytes form a token as defined in RFC 9110, section 5.6.2
+fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(byte))
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_request::*;
+
+    #[test]
+    fn should_look_up_headers_ignoring_case() {
+        let http_request = HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nuser-agent: curl\r\nHOST: localhost\r\n\r\n").unwrap();
+
+        assert_eq!(http_request.headers.get("User-Agent"), Some("curl"));
+        assert_eq!(http_request.headers.get("host"), Some("localhost"));
+        assert!(http_request.headers.contains_key("USER-AGENT"));
+    }
+
+    #[test]
+    fn should_keep_repeated_headers_in_order() {
+        let http_request = HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nAccept: text/html\r\nHost: localhost\r\naccept: application/json\r\n\r\n").unwrap();
+
+        assert_eq!(http_request.headers.get("Accept"), Some("text/html"));
+        assert_eq!(http_request.headers.get_all("Accept"), vec!["text/html", "application/json"]);
+        assert_eq!(http_request.headers.get_joined("Accept"), Some("text/html, application/json".to_string()));
+        assert_eq!(
+            http_request.headers.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["Accept", "Host", "accept"]
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_header_names() {
+        assert!(matches!(HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nContent-Length : 5\r\n\r\n"), Err(HttpServerError::InvalidHeaderSyntax)));
+        assert!(matches!(HttpRequest::from_bytes(b"POST / HTTP/1.1\r\n Host: example.com\r\n\r\n"), Err(HttpServerError::InvalidHeaderSyntax)));
+        assert!(matches!(HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nX(Header): 1\r\n\r\n"), Err(HttpServerError::InvalidHeaderSyntax)));
+        assert!(matches!(HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nNo colon\r\n\r\n"), Err(HttpServerError::InvalidHeaderSyntax)));
+
+        let http_request = HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nContent-Length:5\r\nX-Empty:\r\n\r\n").unwrap();
+        assert_eq!(http_request.headers.get("Content-Length"), Some("5"));
+        assert_eq!(http_request.headers.get("X-Empty"), Some(""));
+    }
+
+    #[test]
+    fn should_parse_methods() {
+        assert_eq!(HttpMethod::from_bytes(b"PATCH"), Some(HttpMethod::PATCH));
+        assert_eq!(HttpMethod::from_bytes(b"TRACE"), Some(HttpMethod::TRACE));
+        assert_eq!(HttpMethod::from_bytes(b"PROPFIND"), Some(HttpMethod::Extension("PROPFIND".to_string())));
+        assert_eq!(HttpMethod::from_bytes(b"get"), Some(HttpMethod::Extension("get".to_string())));
+        assert_eq!(HttpMethod::from_bytes(b"GE(T"), None);
+        assert_eq!(HttpMethod::from_bytes(b""), None);
+        assert_eq!(HttpMethod::from("MKCOL").to_string(), "MKCOL");
+        assert_eq!(HttpMethod::from("DELETE"), HttpMethod::DELETE);
+    }
+
+    #[test]
+    fn should_keep_alive_by_default_on_http_1_1() {
+        assert!(HttpRequest::from_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap().is_keep_alive());
+        assert!(!HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap().is_keep_alive());
+    }
+
+    #[test]
+    fn should_detect_conflicting_framing() {
+        assert!(HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n").unwrap().has_conflicting_framing());
+        assert!(!HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap().has_conflicting_framing());
+    }
+
+    #[test]
+    fn should_close_by_default_on_http_1_0() {
+        assert!(!HttpRequest::from_bytes(b"GET / HTTP/1.0\r\n\r\n").unwrap().is_keep_alive());
+        assert!(HttpRequest::from_bytes(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap().is_keep_alive());
+    }
+}
@@ -1,5 +1,10 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::time::SystemTime;
 use crate::create_enum_and_matchers;
+use crate::http_server::http_body::HttpBody;
+use crate::http_server::http_conditional::EntityTag;
+use crate::http_server::http_date::format_http_date;
 use crate::http_server::http_version::HttpVersion;
 
 #[derive(Default)]
@@ -10,7 +15,7 @@ pub struct HttpResponse {
     pub status_code: HttpStatusCode,
     pub http_version: HttpVersion,
     pub headers: HttpResponseHeader,
-    pub body: Option<String>
+    pub body: Option<HttpBody>
 }
 
 #[derive(Default)]
@@ -18,21 +23,40 @@ pub struct HttpResponseBuilder {
     status_code: HttpStatusCode,
     http_version: HttpVersion,
     headers: HttpResponseHeader,
-    body: Option<String>,
+    body: Option<HttpBody>,
 }
 
 
 
+impl HttpResponseHeader {
+
+    /// Sets a header, replacing any previous value
+    pub fn insert(&mut self, header_key: &str, value: &str) {
+        self.0.insert(header_key.to_owned(), value.to_owned());
+    }
+
+    /// Returns the value of a header if it exists
+    pub fn get(&self, header_key: &str) -> Option<&str> {
+        self.0.get(header_key)
+            .map(|value| value.as_str())
+    }
+
+    /// Removes a header, returning its value if it was set
+    pub fn remove(&mut self, header_key: &str) -> Option<String> {
+        self.0.remove(header_key)
+    }
+}
+
 impl HttpResponseBuilder {
 
     pub fn status_code(mut self, status_code: HttpStatusCode) -> Self {
         self.status_code = status_code;
-        return self
+        self
     }
 
     pub fn http_version(mut self, http_version: HttpVersion) -> Self {
         self.http_version = http_version;
-        return self
+        self
     }
 
     pub fn add_headers(self, headers: Vec<(String, String)>) -> Self {
@@ -40,13 +64,25 @@ impl HttpResponseBuilder {
     }
 
     pub fn add_header(mut self, header: (&str, &str)) -> Self {
-        self.headers.0.insert(header.0.to_owned(), header.1.to_owned());
-        return self
+        self.headers.insert(header.0, header.1);
+        self
+    }
+
+    /// Sets the `ETag` validator of the response
+    pub fn etag(self, etag: &EntityTag) -> Self {
+        self.add_header(("ETag", &etag.to_string()))
+    }
+
+    /// Sets the `Last-Modified` validator of the response
+    pub fn last_modified(self, last_modified: SystemTime) -> Self {
+        self.add_header(("Last-Modified", &format_http_date(last_modified)))
     }
 
-    pub fn body(mut self, body: &str) -> Self {
-        self.body = Some(body.to_owned());
-        self.add_header(("Content-Length", &body.len().to_string()))
+    /// Sets the body and its `Content-Length`. Accepts strings, byte vectors, static byte slices and streamed bodies,
+    /// streamed bodies of unknown length are sent with `Transfer-Encoding: chunked` instead
+    pub fn body<B: Into<HttpBody>>(mut self, body: B) -> Self {
+        set_body(&mut self.headers, &mut self.body, body.into());
+        self
     }
 
     pub fn build(self) -> HttpResponse{
@@ -73,24 +109,85 @@ impl HttpResponse {
         HttpResponseBuilder::default()
     }
 
+    /// Replaces the body, updating `Content-Length` or `Transfer-Encoding` like `HttpResponseBuilder::body`
+    pub fn set_body<B: Into<HttpBody>>(&mut self, body: B) {
+        set_body(&mut self.headers, &mut self.body, body.into());
+    }
+
+    /// Returns the body if it is held in memory
+    pub fn body_bytes(&self) -> Option<&[u8]> {
+        self.body.as_ref().and_then(HttpBody::as_bytes)
+    }
+
+    /// Serializes the response. Streamed bodies are left out, they can only be sent once with `write_to`
     pub fn to_bytes(&self) -> Vec<u8> {
-        self.to_string().as_bytes().to_vec()
+        let body = self.body_bytes()
+            .filter(|_| self.status_code.allows_body())
+            .unwrap_or_default();
+        let mut bytes = Vec::with_capacity(self.head_length() + body.len());
+        self.write_head(&mut bytes).expect("writing into a Vec can not fail");
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// Writes the whole response, reading streamed bodies while they are sent
+    pub fn write_to<W: Write>(self, writer: &mut W) -> std::io::Result<()> {
+        self.write_head(writer)?;
+        let chunked = self.is_chunked();
+        match self.body {
+            Some(body) if self.status_code.allows_body() => body.write_to(writer, chunked),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns true if the body is sent with the chunked transfer coding
+    pub fn is_chunked(&self) -> bool {
+        self.headers.get("Transfer-Encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
     }
 
-    pub fn to_string(&self) -> String {
-        let mut constructed_string = String::new();
+    /// Writes the status line and headers, including the empty line ending them
+    pub fn write_head<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write!(writer, "{} {} {} \r\n", self.http_version, self.status_code.to_int(), self.status_code)?;
+        for (key, value) in self.head_headers() {
+            writer.write_all(key.as_bytes())?;
+            writer.write_all(b": ")?;
+            writer.write_all(value.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        writer.write_all(b"\r\n")
+    }
 
-        let response_signature = format!("{} {} {} \r\n", self.http_version, self.status_code.to_int(), self.status_code.to_string());
-        let mut response_headers = String::new();
+    /// Headers that are sent in the head of the response
+    fn head_headers(&self) -> impl Iterator<Item = (&String, &String)> {
         self.headers.0.iter()
-            .for_each(|(key, value)| response_headers.push_str(&format!("{}: {}\r\n", key, value)));
-        let response_body = format!("\r\n{}", self.body.clone().unwrap_or_default());
+            // A 204 response must not announce a body at all
+            .filter(|(key, _)| self.status_code != HttpStatusCode::NoContent
+                || !(key.eq_ignore_ascii_case("Content-Length") || key.eq_ignore_ascii_case("Transfer-Encoding")))
+    }
 
-        constructed_string.push_str(&response_signature);
-        constructed_string.push_str(&response_headers);
-        constructed_string.push_str(&response_body);
+    /// Estimates the length of the head, used to allocate the serialized response once
+    fn head_length(&self) -> usize {
+        let status_line_length = 32;
+        let headers_length: usize = self.head_headers()
+            .map(|(key, value)| key.len() + value.len() + 4)
+            .sum();
+        status_line_length + headers_length + 2
+    }
+}
+
+fn set_body(headers: &mut HttpResponseHeader, body_slot: &mut Option<HttpBody>, body: HttpBody) {
+    headers.remove("Content-Length");
+    headers.remove("Transfer-Encoding");
+    match body.len() {
+        Some(length) => headers.insert("Content-Length", &length.to_string()),
+        None => headers.insert("Transfer-Encoding", "chunked")
+    }
+    *body_slot = Some(body);
+}
 
-        constructed_string
+impl std::fmt::Display for HttpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()))
     }
 }
 
@@ -110,6 +207,7 @@ create_enum_and_matchers!(
     Created, 201, "Created",
     Accepted, 202, "Accepted",
     NoContent, 204, "No Content",
+    PartialContent, 206, "Partial Content",
     MovedPermanently, 301, "Moved Permanently",
     Found, 302, "Found",
     SeeOther, 303, "See Other",
@@ -124,10 +222,12 @@ create_enum_and_matchers!(
     RequestTimeout, 408, "Request Timeout",
     Conflict, 409, "Conflict",
     Gone, 410, "Gone",
+    PreconditionFailed, 412, "Precondition Failed",
     PayloadTooLarge, 413, "Payload Too Large",
     UriTooLong, 414, "URI Too Long",
     UnsupportedMediaType, 415, "Unsupported Media Type",
     RangeNotSatisfiable, 416, "Range Not Satisfiable",
+    RequestHeaderFieldsTooLarge, 431, "Request Header Fields Too Large",
     InternalServerError, 500, "Internal Server Error",
     NotImplemented, 501, "Not Implemented",
     BadGateway, 502, "Bad Gateway",
@@ -136,4 +236,78 @@ create_enum_and_matchers!(
     HttpVersionNotSupported, 505, "HTTP Version Not Supported"
 );
 
+impl HttpStatusCode {
+    /// Returns false for status codes whose responses never carry a body
+    pub fn allows_body(&self) -> bool {
+        !matches!(self, HttpStatusCode::NoContent | HttpStatusCode::NotModified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_response::*;
+
+    #[test]
+    fn should_serialize_response() {
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .body("hello")
+            .build();
+
+        assert_eq!(http_response.to_string(), "HTTP/1.1 200 OK \r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn should_serialize_binary_body() {
+        let png_signature: &'static [u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .add_header(("Content-Type", "image/png"))
+            .body(png_signature)
+            .build();
 
+        let bytes = http_response.to_bytes();
+        assert!(bytes.ends_with(b"\r\n\r\n\x89PNG\r\n\x1a\n"));
+        assert_eq!(http_response.headers.get("Content-Length"), Some("8"));
+        assert_eq!(http_response.body_bytes(), Some(png_signature));
+    }
+
+    #[test]
+    fn should_write_streamed_body_with_chunked_encoding() {
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .body(HttpBody::from_chunks(vec![b"hello".to_vec()]))
+            .build();
+
+        let mut bytes = Vec::new();
+        http_response.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"HTTP/1.1 200 OK \r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn should_write_streamed_body_with_content_length() {
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::Ok)
+            .body(HttpBody::from_reader(&b"hello"[..], Some(5)))
+            .build();
+
+        let mut bytes = Vec::new();
+        http_response.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"HTTP/1.1 200 OK \r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn should_not_serialize_body_for_no_content_and_not_modified() {
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::NoContent)
+            .body("hello")
+            .build();
+        assert_eq!(http_response.to_string(), "HTTP/1.1 204 No Content \r\n\r\n");
+
+        let http_response = HttpResponse::builder()
+            .status_code(HttpStatusCode::NotModified)
+            .body("hello")
+            .build();
+        assert_eq!(http_response.to_string(), "HTTP/1.1 304 Not Modified \r\nContent-Length: 5\r\n\r\n");
+    }
+}
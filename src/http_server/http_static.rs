@@ -0,0 +1,239 @@
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::error;
+use crate::http_server::http_conditional::{evaluate_preconditions, EntityTag};
+use crate::http_server::http_date::format_http_date;
+use crate::http_server::http_request::{HttpMethod, HttpRequest};
+use crate::http_server::http_range::ranged_reader_response;
+use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
+use crate::http_server::lib::{RequestContext, RouteHandle};
+
+/// Name of the wildcard path variable holding the file path below the mount prefix
+const FILE_PATH_VARIABLE: &str = "file_path";
+const INDEX_FILE: &str = "index.html";
+
+/// Serves the files below a directory on disk
+pub struct StaticFiles {
+    root: PathBuf
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        StaticFiles {
+            root: root.into()
+        }
+    }
+
+    /// Creates a GET route mapping every path below `prefix` to a file below the root directory
+    pub fn route(self, prefix: &str) -> RouteHandle {
+        let route = format!("{}/{{*{}}}", prefix.trim_end_matches('/'), FILE_PATH_VARIABLE);
+        RouteHandle::new(HttpMethod::GET, &route, move |cx| self.serve(cx))
+    }
+
+    /// Answers a request routed through `StaticFiles::route`
+    pub fn serve(&self, cx: &RequestContext) -> HttpResponse {
+        let file_path = cx.path_variables.get(FILE_PATH_VARIABLE)
+            .map_or("", |file_path| file_path.as_str());
+        self.serve_path(cx.http_request, file_path)
+    }
+
+    /// Answers with the file at `file_path`, relative to the root directory.
+    /// Honours `Range` and conditional headers of the request, using the size and modification time of the file as validators
+    pub fn serve_path(&self, http_request: &HttpRequest, file_path: &str) -> HttpResponse {
+        let resolved_path = match self.resolve(file_path) {
+            Ok(resolved_path) => resolved_path,
+            Err(status_code) => return status_code.into()
+        };
+
+        let file = match File::open(&resolved_path) {
+            Ok(file) => file,
+            Err(err) => return io_error_status(err).into()
+        };
+        let metadata = match file.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error_status(err).into()
+        };
+        let last_modified = metadata.modified().ok();
+        let etag = last_modified
+            .and_then(|last_modified| last_modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| EntityTag::strong(&format!("{:x}-{:x}", metadata.len(), age.as_nanos())));
+
+        if let Some(http_response) = evaluate_preconditions(http_request, etag.as_ref(), last_modified) {
+            return http_response
+        }
+
+        // The file is read while the response is sent, HEAD requests never read it
+        let mut http_response = ranged_reader_response(http_request, file, metadata.len(), content_type(&resolved_path));
+        if let Some(etag) = &etag {
+            http_response.headers.insert("ETag", &etag.to_string());
+        }
+        if let Some(last_modified) = last_modified {
+            http_response.headers.insert("Last-Modified", &format_http_date(last_modified));
+        }
+        http_response
+    }
+
+    /// Maps a relative path onto a file below the root directory, never leaving it
+    fn resolve(&self, file_path: &str) -> Result<PathBuf, HttpStatusCode> {
+        let relative_path = Path::new(file_path);
+        if !relative_path.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(HttpStatusCode::Forbidden)
+        }
+
+        let root = self.root.canonicalize().map_err(|err| {
+            error!("Static file root {:?} is not accessible: {}", self.root, err);
+            HttpStatusCode::NotFound
+        })?;
+        let mut resolved_path = root.join(relative_path).canonicalize().map_err(io_error_status)?;
+        // Symbolic links could still point outside of the root directory
+        if !resolved_path.starts_with(&root) {
+            return Err(HttpStatusCode::Forbidden)
+        }
+
+        if resolved_path.is_dir() {
+            resolved_path.push(INDEX_FILE);
+            if !resolved_path.is_file() {
+                return Err(HttpStatusCode::Forbidden)
+            }
+        }
+        Ok(resolved_path)
+    }
+}
+
+fn io_error_status(err: std::io::Error) -> HttpStatusCode {
+    match err.kind() {
+        ErrorKind::NotFound => HttpStatusCode::NotFound,
+        ErrorKind::PermissionDenied => HttpStatusCode::Forbidden,
+        _ => {
+            error!("Failed to read static file: {}", err);
+            HttpStatusCode::InternalServerError
+        }
+    }
+}
+
+/// Guesses the media type of a file from its extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::http_server::http_static::*;
+
+    /// Creates a fresh directory with a few files to serve
+    fn create_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("http_static_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(root.join("css/main.css"), "body {}").unwrap();
+        root
+    }
+
+    fn body(http_response: HttpResponse) -> Vec<u8> {
+        let mut body = Vec::new();
+        http_response.body.unwrap().write_to(&mut body, false).unwrap();
+        body
+    }
+
+    fn get_request() -> HttpRequest {
+        HttpRequest::from_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap()
+    }
+
+    #[test]
+    fn should_serve_file_with_content_type() {
+        let static_files = StaticFiles::new(create_root("file"));
+
+        let http_response = static_files.serve_path(&get_request(), "css/main.css");
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("Content-Type"), Some("text/css; charset=utf-8"));
+        assert_eq!(http_response.headers.get("Content-Length"), Some("7"));
+        assert_eq!(body(http_response), b"body {}");
+    }
+
+    #[test]
+    fn should_serve_file_ranges() {
+        let static_files = StaticFiles::new(create_root("range"));
+        let http_request = HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nRange: bytes=0-3\r\n\r\n").unwrap();
+
+        let http_response = static_files.serve_path(&http_request, "css/main.css");
+        assert_eq!(http_response.status_code, HttpStatusCode::PartialContent);
+        assert_eq!(body(http_response), b"body");
+    }
+
+    #[test]
+    fn should_answer_not_modified_for_current_etag() {
+        let static_files = StaticFiles::new(create_root("etag"));
+
+        let http_response = static_files.serve_path(&get_request(), "css/main.css");
+        let etag = http_response.headers.get("ETag").unwrap();
+        assert!(http_response.headers.get("Last-Modified").is_some());
+
+        let http_request = HttpRequest::from_bytes(format!("GET / HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag).as_bytes()).unwrap();
+        let http_response = static_files.serve_path(&http_request, "css/main.css");
+        assert_eq!(http_response.status_code, HttpStatusCode::NotModified);
+        assert!(http_response.body.is_none());
+    }
+
+    #[test]
+    fn should_serve_index_for_directories() {
+        let static_files = StaticFiles::new(create_root("index"));
+
+        let http_response = static_files.serve_path(&get_request(), "");
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(body(http_response), b"<h1>home</h1>");
+
+        assert_eq!(static_files.serve_path(&get_request(), "empty").status_code, HttpStatusCode::Forbidden);
+    }
+
+    #[test]
+    fn should_answer_not_found_for_missing_files() {
+        let static_files = StaticFiles::new(create_root("missing"));
+
+        assert_eq!(static_files.serve_path(&get_request(), "css/missing.css").status_code, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn should_not_leave_root_directory() {
+        let root = create_root("traversal");
+        let static_files = StaticFiles::new(root.join("css"));
+
+        assert_eq!(static_files.serve_path(&get_request(), "../index.html").status_code, HttpStatusCode::Forbidden);
+        assert_eq!(static_files.serve_path(&get_request(), "/etc/passwd").status_code, HttpStatusCode::Forbidden);
+    }
+
+    #[test]
+    fn should_guess_content_type_from_extension() {
+        assert_eq!(content_type(Path::new("logo.PNG")), "image/png");
+        assert_eq!(content_type(Path::new("app.js")), "text/javascript; charset=utf-8");
+        assert_eq!(content_type(Path::new("archive")), "application/octet-stream");
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::Display;
 use crate::http_server::helper::{split_lines_by_byte};
 use crate::http_server::http_error::Result;
-use crate::http_server::http_error::HttpServerError::InvalidHttpVersionFormat;
+use crate::http_server::http_error::HttpServerError::{InvalidHttpVersionFormat, UnsupportedHttpVersion};
 
 #[derive(Debug)]
 pub struct HttpVersion {
@@ -12,6 +12,9 @@ pub struct HttpVersion {
 
 const BACKSLASH: u8 = 47;
 const DOT: u8 = 46;
+const PROTOCOL_NAME: &str = "HTTP";
+/// The major and minor versions this server speaks, other versions are answered with 505
+const SUPPORTED_VERSIONS: [(&str, &str); 2] = [("1", "0"), ("1", "1")];
 
 impl HttpVersion {
 
@@ -22,6 +25,8 @@ impl HttpVersion {
             minor
         }
     }
+    /// Parses a version like `HTTP/1.1`. Fails with `InvalidHttpVersionFormat` if it is not of the form
+    /// `HTTP/DIGIT.DIGIT` and with `UnsupportedHttpVersion` for versions other than HTTP/1.0 and HTTP/1.1
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         let name_and_version = split_lines_by_byte(bytes, BACKSLASH);
         if name_and_version.len() != 2 { return Err(InvalidHttpVersionFormat) }
@@ -33,12 +38,29 @@ impl HttpVersion {
         let major = String::from_utf8_lossy(version_split[0]).to_string();
         let minor = String::from_utf8_lossy(version_split[1]).to_string();
 
+        // The protocol name is case-sensitive and each version number is a single digit, RFC 9112, section 2.3
+        let is_digit = |number: &str| number.len() == 1 && number.bytes().all(|byte| byte.is_ascii_digit());
+        if name != PROTOCOL_NAME || !is_digit(&major) || !is_digit(&minor) {
+            return Err(InvalidHttpVersionFormat)
+        }
+        if !SUPPORTED_VERSIONS.contains(&(major.as_str(), minor.as_str())) {
+            return Err(UnsupportedHttpVersion)
+        }
+
         Ok(HttpVersion {
             name,
             major,
             minor
         })
     }
+
+    /// Returns true if this version is the given major and minor version or newer
+    pub fn is_at_least(&self, major: u32, minor: u32) -> bool {
+        let version_major = self.major.parse::<u32>().unwrap_or(0);
+        let version_minor = self.minor.parse::<u32>().unwrap_or(0);
+
+        (version_major, version_minor) >= (major, minor)
+    }
 }
 impl Default for HttpVersion {
     fn default() -> Self {
@@ -51,6 +73,34 @@ impl Default for HttpVersion {
 }
 impl Display for HttpVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format!("{}/{}.{}", self.name, self.major, self.minor))
+        write!(f, "{}/{}.{}", self.name, self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_server::http_version::*;
+    use crate::http_server::http_error::HttpServerError;
+
+    #[test]
+    fn should_parse_supported_versions() {
+        assert_eq!(HttpVersion::from_bytes(b"HTTP/1.1").unwrap().to_string(), "HTTP/1.1");
+        assert!(!HttpVersion::from_bytes(b"HTTP/1.0").unwrap().is_at_least(1, 1));
+    }
+
+    #[test]
+    fn should_reject_unsupported_versions() {
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP/2.0"), Err(HttpServerError::UnsupportedHttpVersion)));
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP/1.2"), Err(HttpServerError::UnsupportedHttpVersion)));
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP/0.9"), Err(HttpServerError::UnsupportedHttpVersion)));
+    }
+
+    #[test]
+    fn should_reject_malformed_versions() {
+        assert!(matches!(HttpVersion::from_bytes(b"FOO/1.1"), Err(HttpServerError::InvalidHttpVersionFormat)));
+        assert!(matches!(HttpVersion::from_bytes(b"http/1.1"), Err(HttpServerError::InvalidHttpVersionFormat)));
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP/1.x"), Err(HttpServerError::InvalidHttpVersionFormat)));
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP/11.1"), Err(HttpServerError::InvalidHttpVersionFormat)));
+        assert!(matches!(HttpVersion::from_bytes(b"HTTP1.1"), Err(HttpServerError::InvalidHttpVersionFormat)));
     }
 }
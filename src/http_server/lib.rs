@@ -1,27 +1,90 @@
 use std::collections::{HashMap, VecDeque};
-use std::io::{Read, Seek, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{BufWriter, ErrorKind, Read, Seek, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::ops::{Add, Deref};
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use itertools::Itertools;
 use log::{debug, error, info, trace};
 use crate::http_server::http_request::{HttpMethod, HttpRequest};
 use crate::http_server::http_response::HttpResponse;
-use crate::http_server::http_error::Result;
-use crate::http_server::http_path::{PathCompareResult, RouteMappingPath};
+use crate::http_server::http_error::{HttpServerError, Result};
+use crate::http_server::http_reader::HttpReader;
+use crate::http_server::http_path::{HttpPath, PathCompareResult, PathDecodingPolicy, RouteMappingPath};
 use crate::http_server::http_path::PathCompareResult::NotMatching;
-use crate::http_server::http_response::HttpStatusCode::NotFound;
+use crate::http_server::http_query::QueryParameters;
+use crate::http_server::http_response::HttpStatusCode::{MethodNotAllowed, NoContent, NotFound, ServiceUnavailable};
+use crate::http_server::thread_pool::{OverflowPolicy, ThreadPool};
+use crate::http_server::connection_tracker::{ConnectionGuard, ConnectionTracker};
+use crate::http_server::http_middleware::{Middleware, Next};
+use crate::http_server::http_compression::decode_request_body;
+
+/// Default upper bound for the size of the request line and headers
+const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+/// Default time an idle persistent connection is kept open while waiting for the next request
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default amount of requests served over a single persistent connection before it is closed
+const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+/// Default amount of worker threads handling connections
+const DEFAULT_WORKER_THREADS: usize = 16;
+/// Default amount of accepted connections waiting for a free worker
+const DEFAULT_ACCEPT_QUEUE_SIZE: usize = 64;
+/// Time a rejected connection is drained after its 503 response, so the client can read it before the connection closes
+const REJECT_LINGER_TIMEOUT: Duration = Duration::from_millis(100);
+/// Upper bound for the bytes drained from a rejected connection
+const REJECT_LINGER_MAX_BYTES: usize = 64 * 1024;
+/// Default upper bound for the size of a request body as it is sent
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+/// Default upper bound for the size of a request body after its content coding is decoded
+const DEFAULT_MAX_DECODED_BODY_SIZE: usize = 10 * 1024 * 1024;
 
 pub struct HttpServer {
     listener: Arc<TcpListener>,
-    request_handler: Arc<RequestHandler>
+    request_handler: Arc<RequestHandler>,
+    config: Arc<ServerConfig>
+}
+
+/// Handle to a running HttpServer, used to wait for it or to shut it down
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    connection_tracker: Arc<ConnectionTracker>,
+    join_handle: JoinHandle<()>
 }
 
 #[derive(Default)]
 pub struct HttpServerBuilder {
     listener_addr: Option<String>,
-    route_handles: Vec<RouteHandle>
+    route_handles: Vec<RouteHandle>,
+    middlewares: Vec<Box<dyn Middleware>>,
+    max_header_size: Option<usize>,
+    keep_alive_timeout: Option<Duration>,
+    max_requests_per_connection: Option<usize>,
+    worker_threads: Option<usize>,
+    accept_queue_size: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    path_decoding_policy: PathDecodingPolicy,
+    max_body_size: Option<usize>,
+    max_decoded_body_size: Option<usize>,
+    error_handler: Option<Box<ErrorHandler>>
+}
+
+/// Turns an error raised while reading a request into the response sent back to the client
+pub type ErrorHandler = dyn Fn(&HttpServerError) -> HttpResponse + Send + Sync;
+
+/// Settings shared by every connection of a HttpServer
+struct ServerConfig {
+    max_header_size: usize,
+    keep_alive_timeout: Duration,
+    max_requests_per_connection: usize,
+    worker_threads: usize,
+    accept_queue_size: usize,
+    overflow_policy: OverflowPolicy,
+    path_decoding_policy: PathDecodingPolicy,
+    max_body_size: usize,
+    max_decoded_body_size: usize,
+    error_handler: Box<ErrorHandler>
 }
 
 pub struct RouteHandle {
@@ -32,19 +95,35 @@ pub struct RouteHandle {
 
 pub struct RequestContext<'a> {
     pub http_request: &'a HttpRequest,
-    pub path_variables: HashMap<String, String>
+    pub path_variables: HashMap<String, String>,
+    pub query_parameters: &'a QueryParameters
 }
 struct RequestHandler {
-    route_handles: Vec<RouteHandle>
+    route_handles: Vec<RouteHandle>,
+    middlewares: Vec<Box<dyn Middleware>>
 }
 
+/// Produces the response for a request once routing is done
+type Endpoint<'a> = Box<dyn Fn(&RequestContext) -> HttpResponse + 'a>;
+
 impl<'a> RequestContext<'a> {
     pub fn new(http_request: &'a HttpRequest, path_variables: HashMap<String, String>) -> RequestContext<'a> {
         Self {
             http_request,
-            path_variables
+            path_variables,
+            query_parameters: &http_request.request_line.path.query_parameters
         }
     }
+
+    /// Returns the raw bytes of the request body
+    pub fn body(&self) -> &[u8] {
+        &self.http_request.body
+    }
+
+    /// Returns the request body as a string slice, if it is valid UTF-8
+    pub fn body_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.http_request.body).ok()
+    }
 }
 
 impl RouteHandle {
@@ -68,6 +147,74 @@ impl HttpServerBuilder {
         self
     }
 
+    /// Adds a middleware running around every request, the first one added is the outermost
+    pub fn add_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Sets the maximum size in bytes of the request line and headers, and of the trailers of chunked bodies.
+    /// Larger requests are answered with 431
+    pub fn max_header_size(mut self, max_header_size: usize) -> Self {
+        self.max_header_size = Some(max_header_size);
+        self
+    }
+
+    /// Sets how long an idle persistent connection waits for the next request before it is closed
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(keep_alive_timeout);
+        self
+    }
+
+    /// Sets how many requests are served over a single connection before it is closed
+    pub fn max_requests_per_connection(mut self, max_requests_per_connection: usize) -> Self {
+        self.max_requests_per_connection = Some(max_requests_per_connection.max(1));
+        self
+    }
+
+    /// Sets the amount of worker threads handling connections
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads.max(1));
+        self
+    }
+
+    /// Sets how many accepted connections can wait for a free worker thread
+    pub fn accept_queue_size(mut self, accept_queue_size: usize) -> Self {
+        self.accept_queue_size = Some(accept_queue_size);
+        self
+    }
+
+    /// Sets what happens to new connections once the accept queue is full
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Sets how strictly percent-encoded request paths are checked before routing
+    pub fn path_decoding_policy(mut self, path_decoding_policy: PathDecodingPolicy) -> Self {
+        self.path_decoding_policy = path_decoding_policy;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request body as it is sent, larger bodies are answered with 413
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = Some(max_body_size);
+        self
+    }
+
+    /// Sets the maximum size in bytes of a compressed request body once it is decoded, larger bodies are answered with 413
+    pub fn max_decoded_body_size(mut self, max_decoded_body_size: usize) -> Self {
+        self.max_decoded_body_size = Some(max_decoded_body_size);
+        self
+    }
+
+    /// Overrides how errors raised while reading a request are turned into responses.
+    /// By default the response only carries the status code from `HttpServerError::status_code`
+    pub fn error_handler<F: Fn(&HttpServerError) -> HttpResponse + 'static + Send + Sync>(mut self, error_handler: F) -> Self {
+        self.error_handler = Some(Box::new(error_handler));
+        self
+    }
+
     pub fn build(self) -> HttpServer {
         let addr = self.listener_addr.unwrap_or("".to_string());
 
@@ -76,7 +223,19 @@ impl HttpServerBuilder {
                 TcpListener::bind(&addr)
                     .expect("TcpListener failed to bind to ip and")
             ),
-            request_handler: Arc::new(self.route_handles.into())
+            request_handler: Arc::new(RequestHandler::new(self.route_handles, self.middlewares)),
+            config: Arc::new(ServerConfig {
+                max_header_size: self.max_header_size.unwrap_or(DEFAULT_MAX_HEADER_SIZE),
+                keep_alive_timeout: self.keep_alive_timeout.unwrap_or(DEFAULT_KEEP_ALIVE_TIMEOUT),
+                max_requests_per_connection: self.max_requests_per_connection.unwrap_or(DEFAULT_MAX_REQUESTS_PER_CONNECTION),
+                worker_threads: self.worker_threads.unwrap_or(DEFAULT_WORKER_THREADS),
+                accept_queue_size: self.accept_queue_size.unwrap_or(DEFAULT_ACCEPT_QUEUE_SIZE),
+                overflow_policy: self.overflow_policy,
+                path_decoding_policy: self.path_decoding_policy,
+                max_body_size: self.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE),
+                max_decoded_body_size: self.max_decoded_body_size.unwrap_or(DEFAULT_MAX_DECODED_BODY_SIZE),
+                error_handler: self.error_handler.unwrap_or(Box::new(|err| err.into()))
+            })
         }
 
     }
@@ -88,91 +247,319 @@ impl HttpServer {
         HttpServerBuilder::default()
     }
 
-    pub fn run(self) -> JoinHandle<()> {
+    pub fn run(self) -> ServerHandle {
+        let local_addr = self.listener.local_addr().expect("Listening address doesnt exist");
+        info!("Server listening on: {}", local_addr);
 
-        info!("Server listening on: {}", self.listener.local_addr().expect("Listening address doesnt exist").to_string());
         let tcp_listener = self.listener.clone();
         let request_handler = self.request_handler.clone();
+        let config = self.config.clone();
+        let connection_tracker = Arc::new(ConnectionTracker::default());
+        let accept_tracker = connection_tracker.clone();
 
         let join_handle = thread::spawn(move || {
+            let handler_config = config.clone();
+            let handler_tracker = accept_tracker.clone();
+            let thread_pool = ThreadPool::new(
+                config.worker_threads,
+                config.accept_queue_size,
+                move |(tcp_stream, connection)| HttpServer::handle_tcp_stream(tcp_stream, connection, &request_handler, &handler_config, &handler_tracker)
+            );
+
             for stream_result in tcp_listener.incoming() {
-                match stream_result {
-                    Ok(result) => {
-                        let handler_clone = request_handler.clone();
-                        thread::spawn(move || HttpServer::handle_tcp_stream(result, handler_clone));
-                    },
+                if accept_tracker.is_shutting_down() {
+                    break
+                }
+                let tcp_stream = match stream_result {
+                    Ok(tcp_stream) => tcp_stream,
                     Err(err) => {
                         error!("Invalid TcpStream received: {}", err);
+                        continue
                     }
                 };
+                // Tracked before it is queued, so shutting down also drains and closes connections waiting for a worker
+                let connection = match accept_tracker.register(&tcp_stream) {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        error!("Failed to track connection, closing it: {}", err);
+                        continue
+                    }
+                };
+                match config.overflow_policy {
+                    OverflowPolicy::Block => thread_pool.execute((tcp_stream, connection)),
+                    OverflowPolicy::Reject => if let Err((tcp_stream, _connection)) = thread_pool.try_execute((tcp_stream, connection)) {
+                        debug!("Accept queue is full, rejecting connection");
+                        let mut http_response: HttpResponse = ServiceUnavailable.into();
+                        http_response.headers.insert("Connection", "close");
+                        if HttpServer::send_http_response(&tcp_stream, http_response) {
+                            HttpServer::linger_close(&tcp_stream);
+                        }
+                    }
+                }
             }
+
+            info!("Server stopped accepting connections on: {}", local_addr);
+            thread_pool.join();
         });
-        join_handle
+
+        ServerHandle {
+            local_addr,
+            connection_tracker,
+            join_handle
+        }
     }
 
-    fn handle_tcp_stream(mut tcp_stream: TcpStream, request_handler: Arc<RequestHandler>) {
+    fn handle_tcp_stream(tcp_stream: TcpStream, connection: ConnectionGuard, request_handler: &RequestHandler, config: &ServerConfig, connection_tracker: &ConnectionTracker) {
         debug!("Accepted new connection from: {}", tcp_stream.peer_addr().expect("Invalid peer address"));
 
-        let mut buffer = [0; 128];
-        tcp_stream.read(&mut buffer).unwrap();
+        if let Err(err) = tcp_stream.set_read_timeout(Some(config.keep_alive_timeout)) {
+            error!("Failed to set read timeout on TcpStream: {}", err);
+        }
+
+        let mut reader = HttpReader::new(&tcp_stream);
+        let mut served_requests = 0;
+
+        loop {
+            // The first request of a connection is always served, even if it was queued while shutting down
+            if served_requests > 0 && !connection.set_idle() {
+                return
+            }
+            let http_request = match HttpServer::read_http_request(&mut reader, config) {
+                Ok(http_request) => http_request,
+                Err(HttpServerError::ConnectionClosed) => {
+                    debug!("Connection closed by client");
+                    return
+                }
+                Err(HttpServerError::IoError(err)) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    debug!("Connection idle for longer than {:?}, closing it", config.keep_alive_timeout);
+                    return
+                }
+                Err(HttpServerError::IoError(err)) => {
+                    error!("Error while reading from TcpStream: {}", err);
+                    return
+                }
+                Err(err) => {
+                    error!("Error while deserializing HttpRequest: {:?}", err);
+                    let mut http_response = (config.error_handler)(&err);
+                    http_response.headers.insert("Connection", "close");
+                    HttpServer::send_http_response(&tcp_stream, http_response);
+                    return
+                }
+            };
+            connection.set_busy();
+            debug!("{:#?}", http_request);
+            served_requests += 1;
+
+            let mut http_response = request_handler.handle(&http_request);
+            // HTTP/1.0 clients do not understand chunked bodies, the end of the body is marked by closing the connection
+            let close_delimited = http_response.is_chunked() && !http_request.request_line.http_version.is_at_least(1, 1);
+            if close_delimited {
+                http_response.headers.remove("Transfer-Encoding");
+            }
+            let keep_alive = http_request.is_keep_alive()
+                && !close_delimited
+                && !http_request.has_conflicting_framing()
+                && served_requests < config.max_requests_per_connection
+                && !connection_tracker.is_shutting_down();
+            http_response.headers.insert("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+            if !HttpServer::send_http_response(&tcp_stream, http_response) || !keep_alive {
+                return
+            }
+        }
+    }
+
+    /// Closes the sending side and discards what the client still sends, for at most `REJECT_LINGER_TIMEOUT`.
+    /// Closing a socket with unread input resets the connection, which can discard the response before the client read it
+    fn linger_close(tcp_stream: &TcpStream) {
+        if tcp_stream.shutdown(Shutdown::Write).is_err() {
+            return
+        }
+
+        let deadline = Instant::now() + REJECT_LINGER_TIMEOUT;
+        let mut buffer = [0; 4096];
+        let mut drained = 0;
+        while drained < REJECT_LINGER_MAX_BYTES {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || tcp_stream.set_read_timeout(Some(remaining)).is_err() {
+                return
+            }
+            match (&*tcp_stream).read(&mut buffer) {
+                Ok(0) => return,
+                Ok(read) => drained += read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return
+            }
+        }
+    }
+
+    /// Writes the response to the stream, returning false if it could not be sent completely
+    fn send_http_response(tcp_stream: &TcpStream, http_response: HttpResponse) -> bool {
+        let mut writer = BufWriter::new(tcp_stream);
+        match http_response.write_to(&mut writer).and_then(|()| writer.flush()) {
+            Ok(()) => true,
+            Err(err) => {
+                error!("Error while sending HttpResponse: {}", err);
+                false
+            }
+        }
+    }
+    fn read_http_request<R: Read>(reader: &mut HttpReader<R>, config: &ServerConfig) -> Result<HttpRequest> {
+        let head = reader.read_head(config.max_header_size)?;
+        trace!("Request head in bytes: {:?}", head);
+        trace!("Request head: {:?}", String::from_utf8_lossy(&head));
+
+        let mut http_request = HttpRequest::from_bytes(&head)?;
+        http_request.request_line.path.validate(config.path_decoding_policy)?;
+        reader.read_body(&mut http_request, config.max_body_size, config.max_header_size)?;
+        decode_request_body(&mut http_request, config.max_decoded_body_size)?;
+        Ok(http_request)
+    }
+}
 
-        trace!("Response in bytes: {:?}", buffer);
-        trace!("Response: {:?}", String::from_utf8_lossy(&buffer));
-        let http_request = HttpServer::read_http_request(&buffer)
-            .map_err(|err| {
-                error!("Error while deserializing HttpRequest: {:?}", err)
-            }).unwrap();
-        debug!("{:#?}", http_request);
+impl ServerHandle {
 
-        let http_response = request_handler.handle(&http_request);
-        HttpServer::send_http_response(tcp_stream, http_response);
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
     }
 
-    fn send_http_response(mut tcp_stream: TcpStream, http_response: HttpResponse) {
-        tcp_stream.write(http_response.to_bytes().as_slice()).unwrap();
+    /// Blocks until the server stops
+    pub fn join(self) -> thread::Result<()> {
+        self.join_handle.join()
     }
 
-    fn read_http_request(buffer: &[u8]) -> Result<HttpRequest> {
-        HttpRequest::from_bytes(&buffer)
+    /// Stops accepting new connections and waits up to `timeout` for in-flight requests to finish.
+    /// Connections still open after the timeout are closed forcefully
+    pub fn shutdown(self, timeout: Duration) -> thread::Result<()> {
+        info!("Shutting down server on: {}", self.local_addr);
+        self.connection_tracker.begin_shutdown();
+
+        // The accept loop only notices the shutdown once it accepts another connection
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(if wake_addr.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() });
+        }
+        if let Err(err) = TcpStream::connect(wake_addr) {
+            error!("Failed to wake up the accept loop: {}", err);
+        }
+
+        if !self.connection_tracker.wait_until_drained(timeout) {
+            self.connection_tracker.close_all();
+        }
+        self.join_handle.join()
     }
 }
 
 impl RequestHandler {
+    fn new(mut route_handles: Vec<RouteHandle>, middlewares: Vec<Box<dyn Middleware>>) -> Self {
+        // Routes are tried from most to least specific, so the first match is the best one.
+        // The sort is stable, equally specific routes keep their registration order
+        route_handles.sort_by_key(|route_handle| route_handle.route.specificity());
+
+        RequestHandler {
+            route_handles,
+            middlewares
+        }
+    }
+
     fn handle(&self, http_request: &HttpRequest) -> HttpResponse {
-        let path = &http_request.request_line.path;
-        let routes_available: Vec<&RouteHandle> = self.route_handles.iter()
-            .filter(|route_handle| route_handle.method == http_request.request_line.method)
-            .collect();
-
-        let found_route = routes_available.iter()
-            .find_map(|route| {
-                match route.route.matches(path) {
-                    NotMatching => None,
-                    PathCompareResult::Matching => Some((route, HashMap::default())),
-                    PathCompareResult::MatchingWithVariables(variables_map) => Some((route, variables_map))
-                }
-            });
+        let (endpoint, path_variables) = self.route(http_request);
+        let cx = RequestContext::new(http_request, path_variables);
 
+        let mut http_response = Next::new(&self.middlewares, &endpoint).run(&cx);
+        if http_request.request_line.method == HttpMethod::HEAD {
+            http_response.body = None;
+        }
+        http_response
+    }
+
+    /// Finds what answers the request, together with the path variables of the matched route
+    fn route(&self, http_request: &HttpRequest) -> (Endpoint<'_>, HashMap<String, String>) {
+        let method = &http_request.request_line.method;
+        let mut matching_routes = self.matching_routes(&http_request.request_line.path);
+
+        let found_route = matching_routes.iter()
+            .position(|(route, _)| &route.method == method)
+            // HEAD requests without their own route are answered by the GET route, the body is dropped afterwards
+            .or_else(|| matching_routes.iter().position(|(route, _)| method == &HttpMethod::HEAD && route.method == HttpMethod::GET))
+            .map(|position| matching_routes.swap_remove(position));
 
-        // Run the endpoint function
         match found_route {
-            Some((route, variables_map)) => (route.function)(&RequestContext::new(http_request, variables_map)),
-            None => NotFound.into()
+            Some((route, variables_map)) => (Box::new(&route.function), variables_map),
+            // OPTIONS * asks about the server as a whole rather than a single resource
+            None if method == &HttpMethod::OPTIONS && http_request.request_line.path.raw_target() == "*" => {
+                let allow = allowed_methods(self.route_handles.iter().map(|route| &route.method));
+                (Box::new(move |cx| RequestHandler::options_response(&allow)), HashMap::default())
+            }
+            None if matching_routes.is_empty() => (Box::new(|cx| NotFound.into()), HashMap::default()),
+            None if method == &HttpMethod::OPTIONS => {
+                let allow = allowed_methods(matching_routes.iter().map(|(route, _)| &route.method));
+                (Box::new(move |cx| RequestHandler::options_response(&allow)), HashMap::default())
+            }
+            None => {
+                let allow = allowed_methods(matching_routes.iter().map(|(route, _)| &route.method));
+                (Box::new(move |cx| HttpResponse::builder()
+                    .status_code(MethodNotAllowed)
+                    .add_header(("Allow", &allow))
+                    .build()), HashMap::default())
+            }
         }
     }
+
+    fn options_response(allow: &str) -> HttpResponse {
+        HttpResponse::builder()
+            .status_code(NoContent)
+            .add_header(("Allow", allow))
+            .build()
+    }
+
+    /// Returns every route whose path matches, regardless of the method, ordered from most to least specific
+    fn matching_routes(&self, path: &HttpPath) -> Vec<(&RouteHandle, HashMap<String, String>)> {
+        self.route_handles.iter()
+            .filter_map(|route_handle| {
+                match route_handle.route.matches(path) {
+                    NotMatching => None,
+                    PathCompareResult::Matching => Some((route_handle, HashMap::default())),
+                    PathCompareResult::MatchingWithVariables(variables_map) => Some((route_handle, variables_map))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lists the distinct methods of the given routes as used by the `Allow` header,
+/// including the HEAD and OPTIONS methods the router answers on its own
+fn allowed_methods<'a>(route_methods: impl Iterator<Item = &'a HttpMethod>) -> String {
+    let mut methods: Vec<&HttpMethod> = Vec::new();
+    route_methods
+        .for_each(|method| if !methods.contains(&method) {
+            methods.push(method);
+        });
+    if let Some(get_position) = methods.iter().position(|method| method == &&HttpMethod::GET) {
+        if !methods.contains(&&HttpMethod::HEAD) {
+            methods.insert(get_position + 1, &HttpMethod::HEAD);
+        }
+    }
+    if !methods.contains(&&HttpMethod::OPTIONS) {
+        methods.push(&HttpMethod::OPTIONS);
+    }
+
+    methods.iter().map(|method| method.to_string()).join(", ")
 }
 
 impl From<Vec<RouteHandle>> for RequestHandler {
     fn from(value: Vec<RouteHandle>) -> Self {
-        RequestHandler {
-            route_handles: value,
-        }
+        RequestHandler::new(value, Vec::new())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{mpsc, Mutex};
     use crate::http_server::lib::*;
+    use crate::http_server::http_body::HttpBody;
+    use crate::http_server::http_response::HttpStatusCode;
 
     #[test]
     fn should_bind_server_to_ip() {
@@ -183,5 +570,403 @@ mod tests {
         assert_eq!(http_server.listener.local_addr().unwrap().to_string(), "127.0.0.1:65500");
     }
 
+    #[test]
+    fn should_serve_multiple_requests_over_one_connection() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::GET, "/", |cx| HttpStatusCode::Ok.into()))
+            .max_requests_per_connection(2)
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 1);
+        assert_eq!(response.matches("Connection: close").count(), 1);
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_answer_bad_request_for_whitespace_before_header_colon() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::POST, "/data", |cx| HttpStatusCode::Ok.into()))
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"POST /data HTTP/1.1\r\nContent-Length : 5\r\n\r\nhello").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("Connection: close\r\n"));
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_answer_payload_too_large_for_oversized_content_length() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::POST, "/data", |cx| HttpStatusCode::Ok.into()))
+            .max_body_size(1024)
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"POST /data HTTP/1.1\r\nContent-Length: 1099511627776\r\n\r\nx").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"));
+
+        // The server keeps serving other clients
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"POST /data HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_close_connection_after_request_with_conflicting_framing() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::POST, "/data", |cx| HttpStatusCode::Ok.into()))
+            .add_route(RouteHandle::new(HttpMethod::GET, "/smuggled", |cx| HttpStatusCode::Forbidden.into()))
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"POST /data HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("403 Forbidden"));
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_stream_bodies_of_unknown_length() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::GET, "/export", |cx| HttpResponse::builder()
+                .status_code(HttpStatusCode::Ok)
+                .body(HttpBody::from_chunks((1..=3).map(|row| format!("row {}\n", row).into_bytes())))
+                .build()))
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"GET /export HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("\r\n\r\n6\r\nrow 1\n\r\n6\r\nrow 2\n\r\n6\r\nrow 3\n\r\n0\r\n\r\n"));
+
+        // HTTP/1.0 clients get the raw body and the connection is closed after it
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"GET /export HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nrow 1\nrow 2\nrow 3\n"));
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_reject_connections_when_workers_are_busy() {
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let (started_sender, release_receiver) = (Mutex::new(started_sender), Mutex::new(release_receiver));
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .worker_threads(1)
+            .accept_queue_size(0)
+            .overflow_policy(OverflowPolicy::Reject)
+            .add_route(RouteHandle::new(HttpMethod::GET, "/block", move |cx| {
+                started_sender.lock().unwrap().send(()).unwrap();
+                release_receiver.lock().unwrap().recv().ok();
+                HttpStatusCode::Ok.into()
+            }))
+            .build()
+            .run();
+
+        // Keeps the only worker busy in the handler. Connections are rejected until the worker waits for them
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let busy_stream = 'connect: loop {
+            let mut busy_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+            busy_stream.write_all(b"GET /block HTTP/1.1\r\n\r\n").unwrap();
+            busy_stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+            loop {
+                assert!(std::time::Instant::now() < deadline, "worker never picked up a connection");
+                if started_receiver.try_recv().is_ok() {
+                    break 'connect busy_stream
+                }
+                match busy_stream.read(&mut [0; 1]) {
+                    Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+                    _ => continue 'connect
+                }
+            }
+        };
+
+        // Clients send their request before reading, the unread request must not turn the 503 into a connection reset
+        for _ in 0..10 {
+            let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+            tcp_stream.write_all(b"GET /block HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            tcp_stream.read_to_string(&mut response).unwrap();
+
+            assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        }
+        drop(release_sender);
+        drop(busy_stream);
+        server_handle.shutdown(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn should_finish_in_flight_requests_on_shutdown() {
+        let (started_sender, started_receiver) = mpsc::channel();
+        let started_sender = Mutex::new(started_sender);
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::GET, "/slow", move |cx| {
+                started_sender.lock().unwrap().send(()).unwrap();
+                thread::sleep(Duration::from_millis(200));
+                HttpStatusCode::Ok.into()
+            }))
+            .build()
+            .run();
+        let local_addr = server_handle.local_addr();
+
+        let mut tcp_stream = TcpStream::connect(local_addr).unwrap();
+        tcp_stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        started_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        server_handle.shutdown(Duration::from_secs(5)).unwrap();
+
+        let mut response = String::new();
+        tcp_stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+        assert!(TcpStream::connect(local_addr).is_err());
+    }
+
+    #[test]
+    fn should_close_idle_connections_on_shutdown() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .add_route(RouteHandle::new(HttpMethod::GET, "/", |cx| HttpStatusCode::Ok.into()))
+            .keep_alive_timeout(Duration::from_secs(30))
+            .build()
+            .run();
+
+        let mut tcp_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        tcp_stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        // Once the response arrived the connection waits for its next request
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            tcp_stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+        assert!(String::from_utf8_lossy(&response).contains("Connection: keep-alive"));
+
+        let shutdown_start = std::time::Instant::now();
+        server_handle.shutdown(Duration::from_secs(5)).unwrap();
+        assert!(shutdown_start.elapsed() < Duration::from_secs(1));
 
-}
\ No newline at end of file
+        let mut remaining = Vec::new();
+        tcp_stream.read_to_end(&mut remaining).unwrap();
+        assert!(remaining.is_empty());
+    }
+
+    #[test]
+    fn should_force_close_connections_after_shutdown_timeout() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .keep_alive_timeout(Duration::from_secs(30))
+            .build()
+            .run();
+
+        // Never sends a request, so the connection is stuck waiting for its first one
+        let _stalled_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        wait_for_connections(&server_handle, 1);
+
+        let shutdown_start = std::time::Instant::now();
+        server_handle.shutdown(Duration::from_millis(200)).unwrap();
+        assert!(shutdown_start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Waits until the server tracks `expected` open connections, including the ones waiting for a worker
+    fn wait_for_connections(server_handle: &ServerHandle, expected: usize) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while server_handle.connection_tracker.connection_count() != expected {
+            assert!(std::time::Instant::now() < deadline, "server did not reach {} connections", expected);
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn should_close_queued_connections_after_shutdown_timeout() {
+        let server_handle = HttpServer::builder()
+            .listener("127.0.0.1:0")
+            .worker_threads(1)
+            .keep_alive_timeout(Duration::from_secs(30))
+            .build()
+            .run();
+
+        // The first connection occupies the only worker, the second one waits in the accept queue. Neither sends anything
+        let _stalled_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        let mut queued_stream = TcpStream::connect(server_handle.local_addr()).unwrap();
+        wait_for_connections(&server_handle, 2);
+
+        let shutdown_start = std::time::Instant::now();
+        server_handle.shutdown(Duration::from_millis(200)).unwrap();
+        assert!(shutdown_start.elapsed() < Duration::from_secs(1));
+
+        let mut response = Vec::new();
+        assert!(queued_stream.read_to_end(&mut response).map_or(true, |_| response.is_empty()));
+    }
+
+    #[test]
+    fn should_prefer_specific_routes_regardless_of_registration_order() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::GET, "/users/{*rest}", |cx| HttpStatusCode::Gone.into()),
+            RouteHandle::new(HttpMethod::GET, "/users/{id}", |cx| HttpStatusCode::Accepted.into()),
+            RouteHandle::new(HttpMethod::GET, "/users/me", |cx| HttpStatusCode::Ok.into())
+        ].into();
+
+        let status_code_for = |raw_request: &[u8]| request_handler.handle(&HttpRequest::from_bytes(raw_request).unwrap()).status_code;
+
+        assert_eq!(status_code_for(b"GET /users/me HTTP/1.1\r\n\r\n"), HttpStatusCode::Ok);
+        assert_eq!(status_code_for(b"GET /users/42 HTTP/1.1\r\n\r\n"), HttpStatusCode::Accepted);
+        assert_eq!(status_code_for(b"GET /users/42/posts HTTP/1.1\r\n\r\n"), HttpStatusCode::Gone);
+    }
+
+    #[test]
+    fn should_answer_method_not_allowed_with_allow_header() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::GET, "/data", |cx| HttpStatusCode::Ok.into()),
+            RouteHandle::new(HttpMethod::POST, "/data", |cx| HttpStatusCode::Accepted.into()),
+            RouteHandle::new(HttpMethod::DELETE, "/other", |cx| HttpStatusCode::Ok.into())
+        ].into();
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"PUT /data HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::MethodNotAllowed);
+        assert_eq!(http_response.headers.get("Allow"), Some("GET, HEAD, POST, OPTIONS"));
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"PUT /missing HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn should_answer_head_with_get_route_without_body() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::GET, "/echo/{to_echo}", |cx| HttpResponse::builder()
+                .status_code(HttpStatusCode::Ok)
+                .add_header(("Content-Type", "text/plain"))
+                .body("hello")
+                .build())
+        ].into();
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"HEAD /echo/hello HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("Content-Length"), Some("5"));
+        assert_eq!(http_response.headers.get("Content-Type"), Some("text/plain"));
+        assert!(http_response.body.is_none());
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"POST /echo/hello HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.headers.get("Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn should_answer_options_with_allowed_methods() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::GET, "/data", |cx| HttpStatusCode::Ok.into()),
+            RouteHandle::new(HttpMethod::POST, "/data/{number}", |cx| HttpStatusCode::Accepted.into()),
+            RouteHandle::new(HttpMethod::DELETE, "/data/{number}", |cx| HttpStatusCode::Ok.into())
+        ].into();
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"OPTIONS /data/1 HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::NoContent);
+        assert_eq!(http_response.headers.get("Allow"), Some("POST, DELETE, OPTIONS"));
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::NoContent);
+        assert_eq!(http_response.headers.get("Allow"), Some("GET, HEAD, POST, DELETE, OPTIONS"));
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"OPTIONS /missing HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::NotFound);
+    }
+
+    #[test]
+    fn should_prefer_registered_options_route() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::GET, "/data", |cx| HttpStatusCode::Ok.into()),
+            RouteHandle::new(HttpMethod::OPTIONS, "/data", |cx| HttpStatusCode::Ok.into())
+        ].into();
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"OPTIONS /data HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("Allow"), None);
+    }
+
+    #[test]
+    fn should_route_extension_methods() {
+        let request_handler: RequestHandler = vec![
+            RouteHandle::new(HttpMethod::PATCH, "/data", |cx| HttpStatusCode::Accepted.into()),
+            RouteHandle::new(HttpMethod::from("PROPFIND"), "/data", |cx| HttpStatusCode::Ok.into())
+        ].into();
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"PATCH /data HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Accepted);
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"PROPFIND /data HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"MKCOL /data HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::MethodNotAllowed);
+        assert_eq!(http_response.headers.get("Allow"), Some("PATCH, PROPFIND, OPTIONS"));
+    }
+
+    #[test]
+    fn should_run_middlewares_around_handlers_in_order() {
+        let middlewares: Vec<Box<dyn Middleware>> = vec![
+            Box::new(|cx: &RequestContext, next: Next| {
+                let mut http_response = next.run(cx);
+                http_response.headers.insert("X-Outer", "true");
+                http_response
+            }),
+            Box::new(|cx: &RequestContext, next: Next| match cx.http_request.headers.get("Authorization") {
+                Some(_) => next.run(cx),
+                None => HttpStatusCode::Unauthorized.into()
+            })
+        ];
+        let request_handler = RequestHandler::new(
+            vec![RouteHandle::new(HttpMethod::GET, "/secret", |cx| HttpStatusCode::Ok.into())],
+            middlewares
+        );
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"GET /secret HTTP/1.1\r\nAuthorization: token\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Ok);
+        assert_eq!(http_response.headers.get("X-Outer"), Some("true"));
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"GET /secret HTTP/1.1\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::Unauthorized);
+        assert_eq!(http_response.headers.get("X-Outer"), Some("true"));
+
+        let http_response = request_handler.handle(&HttpRequest::from_bytes(b"GET /missing HTTP/1.1\r\nAuthorization: token\r\n\r\n").unwrap());
+        assert_eq!(http_response.status_code, HttpStatusCode::NotFound);
+        assert_eq!(http_response.headers.get("X-Outer"), Some("true"));
+    }
+}
@@ -2,9 +2,22 @@ use crate::http_server::http_error::HttpServerError;
 
 pub mod http_request;
 pub mod http_response;
+pub mod http_body;
 mod helper;
 pub mod http_error;
 pub mod http_version;
 pub mod http_path;
+pub mod http_query;
+pub mod http_reader;
 pub mod lib;
+pub mod http_middleware;
+pub mod http_static;
+pub mod http_range;
+pub mod http_date;
+pub mod http_conditional;
+pub mod http_compression;
+#[cfg(feature = "json")]
+pub mod http_json;
+pub mod thread_pool;
+mod connection_tracker;
 
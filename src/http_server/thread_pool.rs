@@ -0,0 +1,125 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+use log::error;
+
+/// What happens to a new connection when every worker is busy and the accept queue is full
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Stop accepting connections until there is room in the queue again
+    #[default]
+    Block,
+    /// Answer the connection with 503 Service Unavailable and close it
+    Reject
+}
+
+/// Fixed amount of worker threads consuming jobs from a bounded queue
+pub struct ThreadPool<T: Send + 'static> {
+    sender: SyncSender<T>,
+    workers: Vec<JoinHandle<()>>
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    /// Spawns `size` workers which run `handler` for every job sent to the pool.
+    /// At most `queue_size` jobs wait in the queue while all workers are busy
+    pub fn new<F: Fn(T) + 'static + Send + Sync>(size: usize, queue_size: usize, handler: F) -> Self {
+        let (sender, receiver) = sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        let workers = (0..size.max(1))
+            .map(|id| {
+                let receiver = receiver.clone();
+                let handler = handler.clone();
+                thread::Builder::new()
+                    .name(format!("http-worker-{}", id))
+                    .spawn(move || ThreadPool::work(receiver, handler))
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+
+        ThreadPool {
+            sender,
+            workers
+        }
+    }
+
+    /// Queues a job, waiting for room in the queue if it is full
+    pub fn execute(&self, job: T) {
+        if self.sender.send(job).is_err() {
+            error!("Job sent to a ThreadPool without workers");
+        }
+    }
+
+    /// Queues a job without waiting, handing it back if the queue is full
+    pub fn try_execute(&self, job: T) -> Result<(), T> {
+        match self.sender.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => Err(job)
+        }
+    }
+
+    /// Stops taking new jobs and waits for the workers to finish the queued ones
+    pub fn join(self) {
+        let ThreadPool { sender, workers } = self;
+        drop(sender);
+
+        workers.into_iter()
+            .for_each(|worker| if worker.join().is_err() {
+                error!("Worker thread panicked");
+            });
+    }
+
+    fn work<F: Fn(T)>(receiver: Arc<Mutex<Receiver<T>>>, handler: Arc<F>) {
+        loop {
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return
+            };
+
+            match job {
+                // A panicking handler must not take the worker down with it
+                Ok(job) => if catch_unwind(AssertUnwindSafe(|| handler(job))).is_err() {
+                    error!("Worker thread recovered from a panic while handling a job");
+                },
+                Err(_) => return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use crate::http_server::thread_pool::*;
+
+    #[test]
+    fn should_run_jobs_on_workers() {
+        let (result_sender, result_receiver) = channel();
+        let result_sender = Mutex::new(result_sender);
+        let thread_pool = ThreadPool::new(2, 4, move |job: u32| result_sender.lock().unwrap().send(job * 2).unwrap());
+
+        thread_pool.execute(1);
+        thread_pool.execute(2);
+
+        let mut results: Vec<u32> = result_receiver.iter().take(2).collect();
+        results.sort();
+        assert_eq!(results, vec![2, 4]);
+    }
+
+    #[test]
+    fn should_hand_back_job_when_queue_is_full() {
+        let (release_sender, release_receiver) = channel::<()>();
+        let release_receiver = Mutex::new(release_receiver);
+        let thread_pool = ThreadPool::new(1, 1, move |_: u32| { release_receiver.lock().unwrap().recv().ok(); });
+
+        thread_pool.execute(1);
+        thread_pool.execute(2);
+
+        assert_eq!(thread_pool.try_execute(3), Err(3));
+        release_sender.send(()).unwrap();
+        release_sender.send(()).unwrap();
+    }
+}
@@ -7,6 +7,8 @@ use crate::http_server::http_path::ToPathString;
 use crate::http_server::http_request::HttpMethod::{GET, POST};
 use crate::http_server::http_response::{HttpResponse, HttpStatusCode};
 use crate::http_server::lib::{HttpServer, RequestContext, RouteHandle};
+use crate::http_server::http_middleware::Next;
+use crate::http_server::http_compression::Compression;
 
 fn main() {
     env_logger::init();
@@ -15,9 +17,12 @@ fn main() {
 
     let data2 = data.clone();
     let data3 = data.clone();
+    let data4 = data.clone();
 
     let http_server = HttpServer::builder()
         .listener("127.0.0.1:4221")
+        .add_middleware(log_request)
+        .add_middleware(Compression::new())
         .add_route(RouteHandle::new(GET, "/", |cx| HttpStatusCode::Ok.into()))
         .add_route(RouteHandle::new(GET, "/secret", |cx| HttpStatusCode::Forbidden.into()))
         .add_route(RouteHandle::new(GET, "/echo/{to_echo}", echo))
@@ -25,6 +30,7 @@ fn main() {
         .add_route(RouteHandle::new(GET, "/user-agent", user_agent))
         .add_route(RouteHandle::new(POST, "/data/{number}", move |cx| post_data(cx, data2.clone())))
         .add_route(RouteHandle::new(GET, "/data", move |cx| get_data(cx, data3.clone())))
+        .add_route(RouteHandle::new(POST, "/data", move |cx| post_data_body(cx, data4.clone())))
         .build();
 
     http_server
@@ -34,6 +40,16 @@ fn main() {
 
 }
 
+fn log_request(cx: &RequestContext, next: Next) -> HttpResponse {
+    let http_response = next.run(cx);
+    log::info!("{} {} -> {}",
+        cx.http_request.request_line.method,
+        cx.http_request.request_line.path.raw_target(),
+        http_response.status_code.to_int()
+    );
+    http_response
+}
+
 fn echo(cx: &RequestContext) -> HttpResponse {
     let to_echo = cx.path_variables.get("to_echo")
         .map_or("".to_string(), |var| var.to_string());
@@ -54,7 +70,7 @@ fn echo_two(cx: &RequestContext) -> HttpResponse {
     HttpResponse::builder()
         .add_header(("Content-Type", "text/plain"))
         .status_code(HttpStatusCode::Ok)
-        .body(&format!("{}/{}", to_echo, to_echo_two))
+        .body(format!("{}/{}", to_echo, to_echo_two))
         .build()
 }
 
@@ -71,7 +87,7 @@ fn user_agent(cx: &RequestContext) -> HttpResponse {
 fn post_data(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
 
     let number = cx.path_variables.get("number")
-        .map_or(None, |number_str| number_str.parse::<i32>().ok());
+        .and_then(|number_str| number_str.parse::<i32>().ok());
 
     match number {
         None => {HttpStatusCode::BadRequest.into()}
@@ -83,6 +99,36 @@ fn post_data(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
     }
 }
 
+fn post_data_body(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
+
+    let number = cx.body_str()
+        .and_then(|body| body.trim().parse::<i32>().ok());
+
+    match number {
+        None => {HttpStatusCode::BadRequest.into()}
+        Some(number) => {
+            let mut data = data.lock().unwrap();
+            *data = number;
+            HttpStatusCode::Accepted.into()
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+#[derive(serde::Serialize)]
+struct DataResponse {
+    data: i32
+}
+
+#[cfg(feature = "json")]
+fn get_data(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
+
+    let data = *data.lock().unwrap();
+
+    HttpResponse::json(&DataResponse { data })
+}
+
+#[cfg(not(feature = "json"))]
 fn get_data(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
 
     let data = *data.lock().unwrap();
@@ -90,6 +136,6 @@ fn get_data(cx: &RequestContext, data: Arc<Mutex<i32>>) -> HttpResponse {
     HttpResponse::builder()
         .status_code(HttpStatusCode::Ok)
         .add_header(("Content-Type", "application/json"))
-        .body(&format!("{{data:{}}}", data))
+        .body(format!("{{\"data\":{}}}", data))
         .build()
-}
\ No newline at end of file
+}